@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use tracing::debug;
+
+use crate::hosts::{is_valid_host_name, SUPPORTED_RECORD_TYPES};
+use crate::records::{Endpoint, RecordType, Records};
+
+// Lowercase a DNS name and strip its trailing dot
+pub fn normalize_name(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+// Parse a target as an address matching the record type
+pub fn parse_target(record_type: RecordType, target: &str) -> Result<IpAddr, String> {
+    let ip: IpAddr = target.trim().parse()
+        .map_err(|_| format!("target {target:?} isn't an IP address"))?;
+    match (record_type, ip) {
+        (RecordType::A, IpAddr::V4(_)) | (RecordType::AAAA, IpAddr::V6(_)) => Ok(ip),
+        _ => Err(format!("target {target:?} doesn't match record type {record_type:?}")),
+    }
+}
+
+// Adjust one endpoint to what the hosts store can hold.
+// Return None when nothing storable remains, with the list of adjustments made.
+pub fn adjust_endpoint(mut endpoint: Endpoint) -> (Option<Endpoint>, Vec<String>) {
+    let mut notes: Vec<String> = Vec::new();
+
+    let name = normalize_name(&endpoint.dns_name);
+    if name != endpoint.dns_name {
+        notes.push(format!("normalized name {:?} -> {name:?}", endpoint.dns_name));
+        endpoint.dns_name = name;
+    }
+    if !is_valid_host_name(&endpoint.dns_name) {
+        notes.push(String::from("dropped: name can't be stored in a hosts file"));
+        return (None, notes);
+    }
+    if !SUPPORTED_RECORD_TYPES.contains(&endpoint.record_type) {
+        notes.push(format!("dropped: record type {:?} isn't supported", endpoint.record_type));
+        return (None, notes);
+    }
+
+    let mut targets: BTreeSet<IpAddr> = BTreeSet::new();
+    for target in &endpoint.targets {
+        match parse_target(endpoint.record_type, target) {
+            Ok(ip) => {
+                if !targets.insert(ip) {
+                    notes.push(format!("removed duplicate target {target:?}"));
+                } else if ip.to_string() != *target {
+                    notes.push(format!("canonicalized target {target:?} -> \"{ip}\""));
+                }
+            }
+            Err(e) => notes.push(format!("removed invalid {e}")),
+        }
+    }
+    if targets.is_empty() {
+        notes.push(String::from("dropped: no valid target"));
+        return (None, notes);
+    }
+    endpoint.targets = targets.iter().map(IpAddr::to_string).collect();
+
+    if endpoint.set_identifier.take().is_some() {
+        notes.push(String::from("removed set identifier"));
+    }
+    if endpoint.record_t_t_l.take().is_some() {
+        notes.push(String::from("removed TTL"));
+    }
+    if endpoint.labels.take().is_some() {
+        notes.push(String::from("removed labels"));
+    }
+    if endpoint.provider_specific.take().is_some() {
+        notes.push(String::from("removed provider specific properties"));
+    }
+
+    (Some(endpoint), notes)
+}
+
+// Adjust endpoints to what the hosts store can hold, merging the ones
+// that share a name and a record type once normalized
+pub fn adjust_endpoints(records: Records) -> Records {
+    let mut adjusted: Records = Vec::new();
+    for endpoint in records {
+        let description = format!("{} {:?}", endpoint.dns_name, endpoint.record_type);
+        let (endpoint, mut notes) = adjust_endpoint(endpoint);
+        if let Some(endpoint) = endpoint {
+            match adjusted.iter_mut().find(|e| e.dns_name == endpoint.dns_name && e.record_type == endpoint.record_type) {
+                Some(existing) => {
+                    let targets: BTreeSet<IpAddr> = existing.targets.iter().chain(&endpoint.targets)
+                        .filter_map(|t| t.parse().ok())
+                        .collect();
+                    existing.targets = targets.iter().map(IpAddr::to_string).collect();
+                    notes.push(String::from("merged into a previous endpoint with the same name and type"));
+                }
+                None => adjusted.push(endpoint),
+            }
+        }
+        if notes.is_empty() {
+            debug!("adjust {description}: unchanged");
+        } else {
+            debug!("adjust {description}: {}", notes.join("; "));
+        }
+    }
+    adjusted
+}
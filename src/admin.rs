@@ -0,0 +1,306 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use salvo::http::header::AUTHORIZATION;
+use salvo::oapi::extract::{JsonBody, PathParam, QueryParam};
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::changes::ChangeReport;
+use crate::client::obtain_client;
+use crate::config::CONFIG;
+use crate::dryrun::{DryRun, LAST_DRY_RUN};
+use crate::error::{Error, Result};
+use crate::history::{diff_revisions, find_revision, list_revisions, Revision, RevisionDiff, RevisionInfo};
+use crate::adjust::normalize_name;
+use crate::hosts::{current_host, is_valid_host_name, parse_hosts, HostsSnapshot};
+use crate::logging::obtain_log_level;
+use crate::writer::{obtain_writer, Mutation};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+// Compare tokens without leaking the position of the first difference
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Require `Authorization: Bearer <ADMIN_TOKEN>` on the admin routes,
+// every request is rejected when no token is configured
+#[handler]
+pub async fn authenticate(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let Some(token) = &CONFIG.admin_token else {
+        res.render(Error::Unauthorized("admin API disabled, ADMIN_TOKEN isn't set"));
+        ctrl.skip_rest();
+        return;
+    };
+    let provided = req.headers().get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if provided.is_some_and(|p| same_token(p.trim().as_bytes(), token.as_bytes())) {
+        ctrl.call_next(req, depot, res).await;
+    } else {
+        debug!("admin request without a valid token");
+        res.render(Error::Unauthorized("missing or invalid bearer token"));
+        ctrl.skip_rest();
+    }
+}
+
+// One name of the hosts store
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct HostEntry {
+    pub name: String,
+    /// Addresses, sorted
+    pub addresses: Vec<String>,
+    /// Pinned by an operator, external-dns changes to the name are skipped
+    pub owned: bool,
+}
+
+// Page of the hosts store, sorted by name
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct HostPage {
+    /// Names matching the search
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub items: Vec<HostEntry>,
+}
+
+// Addresses of a name set by an operator
+#[derive(Deserialize, ToSchema, Debug, Clone)]
+pub struct HostUpdate {
+    /// IPv4 and IPv6 addresses of the name
+    pub addresses: Vec<String>,
+    /// Pin the name so external-dns can't change it, true by default
+    #[serde(default = "default_owned")]
+    pub owned: bool,
+}
+
+fn default_owned() -> bool {
+    true
+}
+
+// Log filter of the running provider
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Directives in the RUST_LOG syntax, e.g. `info,host_webhook_provider=debug`
+    pub filter: String,
+}
+
+fn entry(snapshot: &HostsSnapshot, name: &str) -> Option<HostEntry> {
+    let addresses = snapshot.records.get(name);
+    if addresses.is_none() && !snapshot.owned.contains(name) {
+        return None;
+    }
+    let mut addresses: Vec<String> = addresses.into_iter().flatten().cloned().collect();
+    addresses.sort();
+    Some(HostEntry { name: name.to_string(), addresses, owned: snapshot.owned.contains(name) })
+}
+
+// Names of the snapshot matching a search on names and addresses, sorted
+pub fn search_hosts(snapshot: &HostsSnapshot, search: Option<&str>) -> Vec<HostEntry> {
+    let search = search.map(str::to_lowercase);
+    let names: BTreeSet<&String> = snapshot.records.keys().chain(&snapshot.owned).collect();
+    names.into_iter()
+        .filter_map(|name| entry(snapshot, name))
+        .filter(|e| match &search {
+            Some(s) => e.name.contains(s.as_str()) || e.addresses.iter().any(|a| a.contains(s.as_str())),
+            None => true,
+        })
+        .collect()
+}
+
+fn admin_writes_allowed() -> Result<()> {
+    if CONFIG.dry_run {
+        return Err(Error::Conflict("the hosts can't be modified in dry-run mode".into()));
+    }
+    Ok(())
+}
+
+/// List the names of the hosts store, optionally filtered by a search on names and addresses
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 502),
+)]
+pub async fn get_hosts(
+    search: QueryParam<String, false>,
+    offset: QueryParam<usize, false>,
+    limit: QueryParam<usize, false>,
+    depot: &mut Depot,
+) -> Result<Json<HostPage>> {
+    let snapshot = current_host(&obtain_client(depot)).await?;
+    let items = search_hosts(&snapshot, search.as_deref());
+    let offset = offset.into_inner().unwrap_or_default();
+    let limit = limit.into_inner().unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    Ok(Json(HostPage {
+        total: items.len(),
+        offset,
+        limit,
+        items: items.into_iter().skip(offset).take(limit).collect(),
+    }))
+}
+
+/// One name of the hosts store
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 404, 502),
+)]
+pub async fn get_host(name: PathParam<String>, depot: &mut Depot) -> Result<Json<HostEntry>> {
+    let name = normalize_name(&name);
+    let snapshot = current_host(&obtain_client(depot)).await?;
+    entry(&snapshot, &name)
+        .map(Json)
+        .ok_or_else(|| Error::NotFound(format!("host {name}")))
+}
+
+/// Set the addresses of a name, pinned by default
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 400, 401, 409, 422, 502, 503),
+)]
+pub async fn put_host(name: PathParam<String>, body: JsonBody<HostUpdate>, depot: &mut Depot) -> Result<Json<HostEntry>> {
+    admin_writes_allowed()?;
+    let name = normalize_name(&name);
+    let update = body.into_inner();
+    let mut errors: Vec<String> = Vec::new();
+    if !is_valid_host_name(&name) {
+        errors.push(format!("{name}: name can't be stored in a hosts file"));
+    }
+    if update.addresses.is_empty() {
+        errors.push(format!("{name}: at least one address is required"));
+    }
+    let mut addresses: BTreeSet<IpAddr> = BTreeSet::new();
+    for address in &update.addresses {
+        match address.trim().parse::<IpAddr>() {
+            Ok(ip) => { addresses.insert(ip); }
+            Err(_) => errors.push(format!("{name}: {address:?} isn't an IP address")),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::validation("invalid host", errors));
+    }
+
+    let mut addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+    addresses.sort();
+    let mutation = Mutation::SetHost { name: name.clone(), addresses: addresses.clone(), owned: update.owned };
+    obtain_writer(depot).submit(mutation).await?;
+    info!("admin set {name} -> {} (owned={})", addresses.join(","), update.owned);
+    Ok(Json(HostEntry { name, addresses, owned: update.owned }))
+}
+
+/// Remove a name and its operator ownership
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(204, 401, 404, 409, 502, 503),
+)]
+pub async fn delete_host(name: PathParam<String>, depot: &mut Depot, res: &mut Response) -> Result<()> {
+    admin_writes_allowed()?;
+    let name = normalize_name(&name);
+    obtain_writer(depot).submit(Mutation::DeleteHost { name: name.clone() }).await?;
+    info!("admin delete {name}");
+    res.status_code(StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+/// Last change set evaluated in dry-run, with the diff of the hosts content
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 404),
+)]
+pub async fn get_dry_run() -> Result<Json<DryRun>> {
+    LAST_DRY_RUN.read().unwrap().clone()
+        .map(Json)
+        .ok_or_else(|| Error::NotFound("no change set evaluated in dry-run yet".into()))
+}
+
+/// Revisions of the hosts content, newest first
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 502),
+)]
+pub async fn get_revisions(depot: &mut Depot) -> Result<Json<Vec<RevisionInfo>>> {
+    let mut revisions = list_revisions(&obtain_client(depot)).await?;
+    revisions.reverse();
+    Ok(Json(revisions))
+}
+
+/// One revision of the hosts content
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 404, 502),
+)]
+pub async fn get_revision(revision: PathParam<u64>, depot: &mut Depot) -> Result<Json<Revision>> {
+    Ok(Json(find_revision(&obtain_client(depot), *revision).await?))
+}
+
+/// Difference between two revisions of the hosts content
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 404, 502),
+)]
+pub async fn get_revision_diff(revision: PathParam<u64>, to: PathParam<u64>, depot: &mut Depot) -> Result<Json<RevisionDiff>> {
+    let client = obtain_client(depot);
+    let from = find_revision(&client, *revision).await?;
+    let to = find_revision(&client, *to).await?;
+    Ok(Json(diff_revisions(&from, &to)))
+}
+
+/// Restore a revision of the hosts content, recorded as a new revision
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 404, 409, 502, 503),
+)]
+pub async fn post_rollback(revision: PathParam<u64>, depot: &mut Depot) -> Result<Json<ChangeReport>> {
+    admin_writes_allowed()?;
+    let revision = find_revision(&obtain_client(depot), *revision).await?;
+    let mutation = Mutation::Replace {
+        records: parse_hosts(&revision.hosts),
+        reason: format!("rollback to revision {}", revision.revision),
+    };
+    Ok(Json(obtain_writer(depot).submit(mutation).await?))
+}
+
+// Admin routes, served on the admin listener
+/// Current log filter
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401),
+)]
+pub async fn get_log_level(depot: &mut Depot) -> Json<LogFilter> {
+    Json(LogFilter { filter: obtain_log_level(depot).filter() })
+}
+
+/// Change the log filter until the next restart
+#[endpoint(
+    tags("admin"),
+    security(("bearer" = [])),
+    status_codes(200, 401, 422, 503),
+)]
+pub async fn put_log_level(body: JsonBody<LogFilter>, depot: &mut Depot) -> Result<Json<LogFilter>> {
+    let filter = obtain_log_level(depot).set_filter(&body.into_inner().filter)?;
+    info!("admin set log filter {filter}");
+    Ok(Json(LogFilter { filter }))
+}
+
+pub fn router() -> Router {
+    Router::with_path("admin")
+        .hoop(authenticate)
+        .push(Router::with_path("hosts").get(get_hosts)
+            .push(Router::with_path("<name>").get(get_host).put(put_host).delete(delete_host)))
+        .push(Router::with_path("dryrun").get(get_dry_run))
+        .push(Router::with_path("loglevel").get(get_log_level).put(put_log_level))
+        .push(Router::with_path("revisions").get(get_revisions)
+            .push(Router::with_path("<revision>").get(get_revision)
+                .push(Router::with_path("diff/<to>").get(get_revision_diff))
+                .push(Router::with_path("rollback").post(post_rollback))))
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::Client;
+use once_cell::sync::Lazy;
+use tracing::{debug, info, warn};
+
+use crate::config::CONFIG;
+use crate::hosts::{get_configmaps, hosts_data, skipped_lines, snapshot_of, HostRecords, HostsSnapshot};
+use crate::metrics::{observe_records, observe_skipped_lines};
+
+pub static CACHE: Lazy<HostsCache> = Lazy::new(HostsCache::default);
+
+// Versions reported by the watch remembered to recognize the ones of the provider
+// writes, the watch may report a write before the write returns
+const WATCHED_VERSIONS: usize = 16;
+
+// In-memory copy of the hosts ConfigMap, kept up to date by a watch
+#[derive(Default)]
+pub struct HostsCache {
+    state: RwLock<CacheState>,
+    synced: AtomicBool,
+}
+
+#[derive(Default)]
+struct CacheState {
+    snapshot: HostsSnapshot,
+    // Version written by the provider that the watch hasn't reported yet
+    written: Option<String>,
+    // Last versions reported by the watch, newest last
+    watched: VecDeque<String>,
+    // Records read from the shards, with the manifest generation they belong to
+    shards: Option<(String, HostRecords)>,
+}
+
+impl HostsCache {
+    // True once the first list of the ConfigMap is done
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Acquire)
+    }
+
+    // Current records, None until the first sync, when the cache is disabled, or when
+    // the records are sharded and the shards of their generation weren't read yet
+    pub fn snapshot(&self) -> Option<HostsSnapshot> {
+        if !self.is_synced() {
+            return None;
+        }
+        let state = self.state.read().unwrap();
+        let mut snapshot = state.snapshot.clone();
+        if !snapshot.shards.is_empty() {
+            match &state.shards {
+                Some((generation, records)) if snapshot.checksum.as_ref() == Some(generation) => {
+                    snapshot.records = records.clone();
+                }
+                _ => return None,
+            }
+        }
+        Some(snapshot)
+    }
+
+    // Keep the records of sharded hosts read from or written to the shards. The
+    // generation is the checksum of the whole content, so the records stay valid
+    // as long as the watched manifest has the same generation.
+    pub fn store_shards(&self, snapshot: &HostsSnapshot) {
+        if snapshot.shards.is_empty() {
+            return;
+        }
+        if let Some(generation) = &snapshot.checksum {
+            self.state.write().unwrap().shards = Some((generation.clone(), snapshot.records.clone()));
+        }
+    }
+
+    // Replace the records after a successful write, unless the watch already reported
+    // the written version, in which case the cached records are as recent or newer
+    pub fn store_written(&self, snapshot: HostsSnapshot) {
+        self.store_shards(&snapshot);
+        let mut state = self.state.write().unwrap();
+        if snapshot.resource_version.as_ref().is_some_and(|version| state.watched.contains(version)) {
+            return;
+        }
+        state.written = snapshot.resource_version.clone();
+        state.snapshot = snapshot;
+    }
+
+    // Replace the records from a watch event. The events before the one of the version
+    // written by the provider are older than the cached records and are ignored, except
+    // with `force` for relists and deletions, that are always current. Return whether
+    // the records were replaced.
+    pub fn store_watched(&self, snapshot: HostsSnapshot, force: bool) -> bool {
+        let mut state = self.state.write().unwrap();
+        if let Some(version) = &snapshot.resource_version {
+            if state.watched.len() == WATCHED_VERSIONS {
+                state.watched.pop_front();
+            }
+            state.watched.push_back(version.clone());
+        }
+        if let Some(written) = &state.written {
+            if !force && snapshot.resource_version.as_ref() != Some(written) {
+                debug!("ignoring hosts version {:?} older than the written {written}", snapshot.resource_version);
+                return false;
+            }
+        }
+        state.written = None;
+        state.snapshot = snapshot;
+        true
+    }
+
+    pub fn mark_synced(&self) {
+        if !self.synced.swap(true, Ordering::AcqRel) {
+            info!("hosts cache synced");
+        }
+    }
+}
+
+// Watch the hosts ConfigMap and keep the cache up to date, never returns
+pub async fn run_watcher(client: Client) {
+    let configmaps = get_configmaps(&client);
+    let config = watcher::Config::default()
+        .fields(&format!("metadata.name={}", CONFIG.host_configmap_name));
+
+    // set when the ConfigMap is listed during a (re)list
+    let mut listed = false;
+    let mut events = watcher(configmaps, config).default_backoff().boxed();
+    loop {
+        match events.try_next().await {
+            Ok(Some(event)) => match event {
+                watcher::Event::Init => listed = false,
+                watcher::Event::InitApply(cm) => {
+                    listed = true;
+                    update(&cm, true);
+                }
+                watcher::Event::InitDone => {
+                    if !listed {
+                        debug!("ConfigMap {} doesn't exist", CONFIG.host_configmap_name);
+                        CACHE.store_watched(HostsSnapshot::default(), true);
+                    }
+                    CACHE.mark_synced();
+                }
+                watcher::Event::Apply(cm) => update(&cm, false),
+                watcher::Event::Delete(_) => {
+                    info!("ConfigMap {} deleted", CONFIG.host_configmap_name);
+                    CACHE.store_watched(HostsSnapshot::default(), true);
+                }
+            },
+            Ok(None) => {
+                warn!("ConfigMap {} watch ended", CONFIG.host_configmap_name);
+                return;
+            }
+            Err(e) => warn!("ConfigMap {} watch error: {e}", CONFIG.host_configmap_name),
+        }
+    }
+}
+
+fn update(cm: &ConfigMap, relisted: bool) {
+    let snapshot = snapshot_of(cm);
+    let (names, version) = (snapshot.records.len(), snapshot.resource_version.clone());
+    // sharded records are observed when read
+    let sharded = !snapshot.shards.is_empty();
+    let records = snapshot.records.clone();
+    if CACHE.store_watched(snapshot, relisted) {
+        if !sharded {
+            observe_records(&records);
+            observe_skipped_lines(skipped_lines(hosts_data(cm)));
+        }
+        debug!("hosts cache updated: {names} names at version {version:?}");
+    }
+}
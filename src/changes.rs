@@ -0,0 +1,399 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use clap::{Args, ValueEnum};
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::adjust::{normalize_name, parse_target};
+use crate::error::{Error, Result};
+use crate::hosts::{is_valid_host_name, HostRecords, SUPPORTED_RECORD_TYPES};
+use crate::records::{Changes, Endpoint, RecordType};
+
+// What to do when a created record already exists with addresses of the same type
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CreateExisting {
+    // Replace the existing addresses by the created ones
+    #[default]
+    Replace,
+    // Keep the existing addresses and add the created ones
+    Merge,
+    // Fail the whole change set
+    Reject,
+}
+
+// What to do when a deleted record doesn't exist
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeleteMissing {
+    // Skip the deletion and report it
+    #[default]
+    Ignore,
+    // Fail the whole change set
+    Reject,
+}
+
+// What to do with update pairs that don't match (different name or type, uneven lists)
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpdateMismatch {
+    // Skip the pair and report it
+    #[default]
+    Skip,
+    // Fail the whole change set
+    Reject,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ChangePolicy {
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ON_CREATE_EXISTING",
+        env = "ON_CREATE_EXISTING",
+        default_value_t = CreateExisting::Replace)]
+    pub on_create_existing: CreateExisting,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ON_DELETE_MISSING",
+        env = "ON_DELETE_MISSING",
+        default_value_t = DeleteMissing::Ignore)]
+    pub on_delete_missing: DeleteMissing,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ON_UPDATE_MISMATCH",
+        env = "ON_UPDATE_MISMATCH",
+        default_value_t = UpdateMismatch::Skip)]
+    pub on_update_mismatch: UpdateMismatch,
+}
+
+// One record set touched by a change set
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordChange {
+    pub name: String,
+    pub record_type: RecordType,
+    /// Addresses before the change, sorted
+    pub before: Vec<String>,
+    /// Addresses after the change, sorted
+    pub after: Vec<String>,
+}
+
+// One change that wasn't applied
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedChange {
+    pub name: String,
+    pub record_type: RecordType,
+    pub reason: String,
+}
+
+// What apply_changes did, in application order
+#[derive(Serialize, ToSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeReport {
+    pub created: Vec<RecordChange>,
+    pub updated: Vec<RecordChange>,
+    pub deleted: Vec<RecordChange>,
+    pub skipped: Vec<SkippedChange>,
+}
+
+impl ChangeReport {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+// Record type stored for a hosts address
+pub fn record_type_of(address: &str) -> RecordType {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => RecordType::A,
+        Ok(IpAddr::V6(_)) => RecordType::AAAA,
+        Err(_) if address.contains(':') => RecordType::AAAA,
+        Err(_) => RecordType::A,
+    }
+}
+
+// Addresses of one record type for a name, sorted
+fn addresses(records: &HostRecords, name: &str, record_type: RecordType) -> Vec<String> {
+    let mut addresses: Vec<String> = records.get(name)
+        .map(|ips| ips.iter().filter(|ip| record_type_of(ip) == record_type).cloned().collect())
+        .unwrap_or_default();
+    addresses.sort();
+    addresses
+}
+
+// Replace the addresses of one record type for a name, removing the name once empty
+fn set_addresses(records: &mut HostRecords, name: &str, record_type: RecordType, new: &[String]) {
+    let ips = records.entry(name.to_string()).or_default();
+    ips.retain(|ip| record_type_of(ip) != record_type);
+    ips.extend(new.iter().cloned());
+    if ips.is_empty() {
+        records.remove(name);
+    }
+}
+
+// Endpoint validated against what a hosts file can store
+struct Target {
+    name: String,
+    record_type: RecordType,
+    addresses: Vec<String>,
+}
+
+// Normalize an endpoint, None when its record type can't be stored. The targets
+// are left out when `with_targets` is false, deletes ignore them.
+fn target(endpoint: &Endpoint, with_targets: bool, errors: &mut Vec<String>) -> Option<Target> {
+    if !SUPPORTED_RECORD_TYPES.contains(&endpoint.record_type) {
+        return None;
+    }
+    let name = normalize_name(&endpoint.dns_name);
+    if !is_valid_host_name(&name) {
+        errors.push(format!("{name}: name can't be stored in a hosts file"));
+    }
+    let mut addresses: Vec<String> = Vec::new();
+    for target in endpoint.targets.iter().filter(|_| with_targets) {
+        match parse_target(endpoint.record_type, target) {
+            Ok(ip) => addresses.push(ip.to_string()),
+            Err(e) => errors.push(format!("{name}: {e}")),
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    Some(Target { name, record_type: endpoint.record_type, addresses })
+}
+
+fn unsupported(endpoint: &Endpoint) -> SkippedChange {
+    SkippedChange {
+        name: normalize_name(&endpoint.dns_name),
+        record_type: endpoint.record_type,
+        reason: format!("record type {:?} isn't supported", endpoint.record_type),
+    }
+}
+
+fn skip(target: &Target, reason: impl Into<String>) -> SkippedChange {
+    SkippedChange { name: target.name.clone(), record_type: target.record_type, reason: reason.into() }
+}
+
+fn owned_skip(endpoint: &Endpoint) -> SkippedChange {
+    SkippedChange {
+        name: normalize_name(&endpoint.dns_name),
+        record_type: endpoint.record_type,
+        reason: String::from("name is operator-owned"),
+    }
+}
+
+// Drop the changes touching operator-owned names, they are reported as skipped.
+// Update pairs are dropped as a whole when either side is owned.
+pub fn without_owned(changes: &Changes, owned: &BTreeSet<String>) -> (Changes, Vec<SkippedChange>) {
+    let mut skipped: Vec<SkippedChange> = Vec::new();
+    if owned.is_empty() {
+        return (changes.clone(), skipped);
+    }
+    let is_owned = |endpoint: &Endpoint| owned.contains(&normalize_name(&endpoint.dns_name));
+
+    let mut keep = |list: &Option<Vec<Endpoint>>| list.as_ref().map(|list| {
+        list.iter()
+            .filter(|endpoint| {
+                if is_owned(endpoint) {
+                    skipped.push(owned_skip(endpoint));
+                }
+                !is_owned(endpoint)
+            })
+            .cloned()
+            .collect::<Vec<Endpoint>>()
+    });
+    let create = keep(&changes.create);
+    let delete = keep(&changes.delete);
+
+    let old = changes.update_old.as_deref().unwrap_or_default();
+    let new = changes.update_new.as_deref().unwrap_or_default();
+    let (mut update_old, mut update_new) = (Vec::new(), Vec::new());
+    for i in 0..old.len().max(new.len()) {
+        let (o, n) = (old.get(i), new.get(i));
+        if let Some(endpoint) = n.filter(|e| is_owned(e)).or(o.filter(|e| is_owned(e))) {
+            skipped.push(owned_skip(endpoint));
+            continue;
+        }
+        update_old.extend(o.cloned());
+        update_new.extend(n.cloned());
+    }
+
+    let changes = Changes {
+        create,
+        delete,
+        update_old: changes.update_old.as_ref().map(|_| update_old),
+        update_new: changes.update_new.as_ref().map(|_| update_new),
+    };
+    (changes, skipped)
+}
+
+// Apply a change set on a copy of the current records.
+//
+// Changes are applied in this order, so a record can be deleted and created again in one set:
+// - delete: remove every address of the record type, the targets are ignored.
+//   Missing records are skipped or rejected according to `on_delete_missing`.
+// - update: pairs UpdateOld[i] with UpdateNew[i], removes the old targets and adds the new ones.
+//   Pairs with different names or types, or without counterpart, are skipped or rejected
+//   according to `on_update_mismatch`. A missing old record is created from the new one.
+// - create: set the addresses of the record type. Existing addresses are replaced, merged
+//   or rejected according to `on_create_existing`.
+//
+// Record types a hosts file can't store are skipped, invalid names or targets fail the set.
+#[instrument(skip_all, fields(
+    create = changes.create.as_ref().map_or(0, Vec::len),
+    update = changes.update_new.as_ref().map_or(0, Vec::len),
+    delete = changes.delete.as_ref().map_or(0, Vec::len),
+))]
+pub fn apply_changes(current: &HostRecords, changes: &Changes, policy: &ChangePolicy) -> Result<(HostRecords, ChangeReport)> {
+    let mut records = current.clone();
+    let mut report = ChangeReport::default();
+    let mut errors: Vec<String> = Vec::new();
+    let mut conflicts: Vec<String> = Vec::new();
+
+    let create = changes.create.as_deref().unwrap_or_default();
+    let delete = changes.delete.as_deref().unwrap_or_default();
+    let update_old = changes.update_old.as_deref().unwrap_or_default();
+    let update_new = changes.update_new.as_deref().unwrap_or_default();
+
+    let mut deletes: Vec<Target> = Vec::new();
+    for endpoint in delete {
+        match target(endpoint, false, &mut errors) {
+            Some(t) => deletes.push(t),
+            None => report.skipped.push(unsupported(endpoint)),
+        }
+    }
+    let mut updates: Vec<(Target, Target)> = Vec::new();
+    for i in 0..update_old.len().max(update_new.len()) {
+        match (update_old.get(i), update_new.get(i)) {
+            (Some(old), Some(new)) => {
+                match (target(old, true, &mut errors), target(new, true, &mut errors)) {
+                    (Some(o), Some(n)) => updates.push((o, n)),
+                    (None, _) => report.skipped.push(unsupported(old)),
+                    (_, None) => report.skipped.push(unsupported(new)),
+                }
+            }
+            (Some(endpoint), None) | (None, Some(endpoint)) => {
+                if let Some(t) = target(endpoint, true, &mut errors) {
+                    let reason = String::from("update has no counterpart");
+                    match policy.on_update_mismatch {
+                        UpdateMismatch::Skip => report.skipped.push(skip(&t, reason)),
+                        UpdateMismatch::Reject => conflicts.push(format!("{}: {reason}", t.name)),
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    let mut creates: Vec<Target> = Vec::new();
+    for endpoint in create {
+        match target(endpoint, true, &mut errors) {
+            Some(t) => creates.push(t),
+            None => report.skipped.push(unsupported(endpoint)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::validation("invalid endpoints in changes", errors));
+    }
+
+    for t in deletes {
+        let before = addresses(&records, &t.name, t.record_type);
+        if before.is_empty() {
+            match policy.on_delete_missing {
+                DeleteMissing::Ignore => report.skipped.push(skip(&t, "record doesn't exist")),
+                DeleteMissing::Reject => conflicts.push(format!("{} {:?}: record doesn't exist", t.name, t.record_type)),
+            }
+            continue;
+        }
+        set_addresses(&mut records, &t.name, t.record_type, &[]);
+        report.deleted.push(RecordChange { name: t.name, record_type: t.record_type, before, after: Vec::new() });
+    }
+
+    for (old, new) in updates {
+        if old.name != new.name || old.record_type != new.record_type {
+            let reason = format!("update from {} {:?} to {} {:?} doesn't match", old.name, old.record_type, new.name, new.record_type);
+            match policy.on_update_mismatch {
+                UpdateMismatch::Skip => report.skipped.push(skip(&new, reason)),
+                UpdateMismatch::Reject => conflicts.push(reason),
+            }
+            continue;
+        }
+        let before = addresses(&records, &new.name, new.record_type);
+        let mut after: Vec<String> = before.iter()
+            .filter(|ip| !old.addresses.contains(ip))
+            .chain(&new.addresses)
+            .cloned()
+            .collect();
+        after.sort();
+        after.dedup();
+        set_addresses(&mut records, &new.name, new.record_type, &after);
+        let change = RecordChange { name: new.name, record_type: new.record_type, before, after };
+        if change.before.is_empty() {
+            report.created.push(change);
+        } else if change.after.is_empty() {
+            report.deleted.push(change);
+        } else {
+            report.updated.push(change);
+        }
+    }
+
+    for t in creates {
+        let before = addresses(&records, &t.name, t.record_type);
+        let after = if before.is_empty() {
+            t.addresses.clone()
+        } else {
+            match policy.on_create_existing {
+                CreateExisting::Replace => t.addresses.clone(),
+                CreateExisting::Merge => {
+                    let mut merged: Vec<String> = before.iter().chain(&t.addresses).cloned().collect();
+                    merged.sort();
+                    merged.dedup();
+                    merged
+                }
+                CreateExisting::Reject => {
+                    conflicts.push(format!("{} {:?}: record already exists", t.name, t.record_type));
+                    continue;
+                }
+            }
+        };
+        set_addresses(&mut records, &t.name, t.record_type, &after);
+        let change = RecordChange { name: t.name, record_type: t.record_type, before, after };
+        if change.before.is_empty() {
+            report.created.push(change);
+        } else {
+            report.updated.push(change);
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(Error::Conflict(conflicts.join(", ")));
+    }
+    Ok((records, report))
+}
+
+// Report of replacing every record by the target ones, e.g. when restoring a revision
+pub fn replace_records(current: &HostRecords, target: &HostRecords) -> ChangeReport {
+    let mut report = ChangeReport::default();
+    let names: BTreeSet<&String> = current.keys().chain(target.keys()).collect();
+    for name in names {
+        for &record_type in SUPPORTED_RECORD_TYPES {
+            let change = RecordChange {
+                name: name.clone(),
+                record_type,
+                before: addresses(current, name, record_type),
+                after: addresses(target, name, record_type),
+            };
+            if change.before == change.after {
+                continue;
+            }
+            if change.before.is_empty() {
+                report.created.push(change);
+            } else if change.after.is_empty() {
+                report.deleted.push(change);
+            } else {
+                report.updated.push(change);
+            }
+        }
+    }
+    report
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use clap::{ArgGroup, Args, Subcommand, ValueEnum};
+use tracing::info;
+
+use crate::changes::{apply_changes, ChangePolicy, CreateExisting};
+use crate::client::build_client;
+use crate::config::CONFIG;
+use crate::dryrun::{hosts_diff, name_changes, PLAN_LABELS};
+use crate::error::{Error, Result};
+use crate::hosts::{format_records_by, parse_hosts, read_host, validate_hosts, HostRecords};
+use crate::records::{check_domain_filter, endpoints, log_report, Changes, Records};
+use crate::writer::{apply_batch, spawn_writer, Mutation};
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    // Serve the webhook, the default
+    Serve,
+    // Export the stored records
+    Export(ExportArgs),
+    // Import records into the store
+    Import(ImportArgs),
+    // Check a hosts file offline
+    Validate(ValidateArgs),
+    // Compare the records of two files offline
+    Diff(DiffArgs),
+    // Apply an external-dns change set to a hosts file offline
+    Apply(ApplyArgs),
+}
+
+// Format of exported and imported records
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    // external-dns endpoints, as returned by GET /records
+    #[default]
+    Json,
+    // hosts file lines
+    Hosts,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Json)]
+    pub format: Format,
+
+    // Output file, defaults to stdout
+    #[arg(
+        long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("mode").required(true).args(["merge", "replace"])))]
+pub struct ImportArgs {
+    // File to import, `-` for stdin
+    pub input: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Json)]
+    pub format: Format,
+
+    // Add the imported addresses to the stored ones
+    #[arg(long)]
+    pub merge: bool,
+
+    // Replace every stored record by the imported ones
+    #[arg(long)]
+    pub replace: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ValidateArgs {
+    // Hosts file to check, `-` for stdin
+    pub file: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DiffArgs {
+    pub a: PathBuf,
+    pub b: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Hosts)]
+    pub format: Format,
+
+    // Exit with 1 when the records differ
+    #[arg(long)]
+    pub exit_code: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ApplyArgs {
+    // external-dns change set, as posted to /records, `-` for stdin
+    #[arg(
+        long,
+        value_name = "FILE")]
+    pub changes: PathBuf,
+
+    // Hosts file the changes apply to
+    #[arg(
+        long,
+        value_name = "FILE")]
+    pub hosts: PathBuf,
+
+    // Resulting hosts file, defaults to stdout
+    #[arg(
+        long, short)]
+    pub output: Option<PathBuf>,
+}
+
+// Content of a file, `-` for stdin
+fn read_input(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        Ok(std::io::read_to_string(std::io::stdin())?)
+    } else {
+        Ok(fs::read_to_string(path)?)
+    }
+}
+
+fn write_output(path: Option<&Path>, content: &str) -> Result<()> {
+    match path {
+        Some(path) => fs::write(path, content)?,
+        None => std::io::stdout().write_all(content.as_bytes())?,
+    }
+    Ok(())
+}
+
+// Render records in an export format
+pub fn render(records: &HostRecords, format: Format) -> Result<String> {
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&endpoints(records))? + "\n"),
+        Format::Hosts => Ok(format_records_by(records, CONFIG.hosts_order)),
+    }
+}
+
+// Parse exported records, endpoints are validated like created records
+pub fn parse(content: &str, format: Format) -> Result<HostRecords> {
+    match format {
+        Format::Json => {
+            let records: Records = serde_json::from_str(content)?;
+            let changes = Changes { create: Some(records), update_old: None, update_new: None, delete: None };
+            let policy = ChangePolicy { on_create_existing: CreateExisting::Merge, ..Default::default() };
+            let (records, report) = apply_changes(&HashMap::new(), &changes, &policy)?;
+            if let Some(skipped) = report.skipped.first() {
+                return Err(Error::validation("unsupported records in import", vec![skipped.reason.clone()]));
+            }
+            Ok(records)
+        }
+        Format::Hosts => Ok(parse_hosts(content)),
+    }
+}
+
+pub async fn export(args: &ExportArgs) -> Result<()> {
+    let client = build_client().await?;
+    let snapshot = read_host(&client).await?;
+    let content = render(&snapshot.records, args.format)?;
+    write_output(args.output.as_deref(), &content)?;
+    info!("exported {} names from ConfigMap {}", snapshot.records.len(), CONFIG.host_configmap_name);
+    Ok(())
+}
+
+pub async fn import(args: &ImportArgs) -> Result<()> {
+    let content = read_input(&args.input)?;
+    let records = parse(&content, args.format)?;
+    let reason = format!("import of {} ({})", args.input.display(), if args.merge { "merge" } else { "replace" });
+    let mutation = if args.merge {
+        Mutation::Merge { records, reason }
+    } else {
+        Mutation::Replace { records, reason }
+    };
+
+    let client = build_client().await?;
+    if CONFIG.dry_run {
+        let current = read_host(&client).await?;
+        let (planned, _) = apply_batch(&current, std::slice::from_ref(&mutation), &CONFIG.change_policy);
+        print!("{}", hosts_diff(&current.records, &planned.records, PLAN_LABELS));
+        return Ok(());
+    }
+    let writer = spawn_writer(client);
+    let report = writer.submit(mutation).await;
+    // the history, events and hooks of the write run after the reply, wait for
+    // them before the process exits
+    writer.close().await;
+    let report = report?;
+    info!("imported into ConfigMap {}: {} created, {} updated, {} deleted",
+        CONFIG.host_configmap_name, report.created.len(), report.updated.len(), report.deleted.len());
+    Ok(())
+}
+
+// Semantic difference between two record sets, one line per changed name
+pub fn describe_changes(before: &HostRecords, after: &HostRecords) -> String {
+    let (added, removed, modified) = name_changes(before, after);
+    let mut lines: Vec<(String, String)> = Vec::new();
+    lines.extend(added.iter().map(|c| (c.name.clone(), format!("+ {} {}", c.name, c.after.join(",")))));
+    lines.extend(removed.iter().map(|c| (c.name.clone(), format!("- {} {}", c.name, c.before.join(",")))));
+    lines.extend(modified.iter().map(|c| (c.name.clone(), format!("~ {} {} -> {}", c.name, c.before.join(","), c.after.join(",")))));
+    lines.sort();
+    lines.into_iter().map(|(_, line)| line + "\n").collect()
+}
+
+pub fn validate(args: &ValidateArgs) -> Result<()> {
+    let content = read_input(&args.file)?;
+    let errors = validate_hosts(&content);
+    if !errors.is_empty() {
+        return Err(Error::validation(format!("invalid hosts file {}", args.file.display()), errors));
+    }
+    let records = parse_hosts(&content);
+    info!("{}: {} names, {} addresses",
+        args.file.display(), records.len(), records.values().map(|ips| ips.len()).sum::<usize>());
+    Ok(())
+}
+
+// Print the difference between two record sets, true when they differ
+pub fn diff(args: &DiffArgs) -> Result<bool> {
+    let a = parse(&read_input(&args.a)?, args.format)?;
+    let b = parse(&read_input(&args.b)?, args.format)?;
+    let changes = describe_changes(&a, &b);
+    print!("{changes}");
+    Ok(!changes.is_empty())
+}
+
+// Apply changes like POST /records would, with the configured domain filter
+// and change policy
+pub fn apply(args: &ApplyArgs) -> Result<()> {
+    let changes: Changes = serde_json::from_str(&read_input(&args.changes)?)?;
+    let current = parse_hosts(&fs::read_to_string(&args.hosts)?);
+    check_domain_filter(&changes)?;
+    let (records, report) = apply_changes(&current, &changes, &CONFIG.change_policy)?;
+    log_report(&report, "");
+    write_output(args.output.as_deref(), &format_records_by(&records, CONFIG.hosts_order))?;
+    info!("applied to {}: {} created, {} updated, {} deleted, {} skipped",
+        args.hosts.display(), report.created.len(), report.updated.len(), report.deleted.len(), report.skipped.len());
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+use salvo::http::header::{HeaderValue, USER_AGENT};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Client, client::ClientBuilder};
+use salvo::Depot;
+
+use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use crate::metrics::KubeMetricsLayer;
+
+// Build the kube client shared by the whole provider from the configuration:
+// explicit kubeconfig path and/or context, else in-cluster or default kubeconfig
+pub async fn build_client() -> Result<Client> {
+    let options = KubeConfigOptions {
+        context: CONFIG.kube_context.clone(),
+        ..Default::default()
+    };
+    let mut config = match (&CONFIG.kubeconfig, &CONFIG.kube_context) {
+        (Some(path), _) => {
+            let kubeconfig = Kubeconfig::read_from(path).map_err(config_error)?;
+            kube::Config::from_custom_kubeconfig(kubeconfig, &options).await.map_err(config_error)?
+        }
+        (None, Some(_)) => kube::Config::from_kubeconfig(&options).await.map_err(config_error)?,
+        (None, None) => kube::Config::infer().await.map_err(config_error)?,
+    };
+    config.connect_timeout = Some(Duration::from_secs(CONFIG.kube_connect_timeout));
+    config.read_timeout = Some(Duration::from_secs(CONFIG.kube_read_timeout));
+    let user_agent = HeaderValue::from_str(&CONFIG.kube_user_agent).map_err(config_error)?;
+    config.headers.push((USER_AGENT, user_agent));
+
+    Ok(ClientBuilder::try_from(config)?.with_layer(&KubeMetricsLayer).build())
+}
+
+fn config_error(e: impl std::fmt::Display) -> Error {
+    Error::KubeConfig(e.to_string())
+}
+
+// Kube client injected in the depot at startup
+pub fn obtain_client(depot: &Depot) -> Client {
+    depot.obtain::<Client>()
+        .expect("kube client isn't injected in the depot")
+        .clone()
+}
@@ -1,12 +1,26 @@
 use once_cell::sync::Lazy;
 use clap::Parser;
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use regex::Regex;
+use salvo::oapi::ToSchema;
+
+use crate::changes::ChangePolicy;
+use crate::cli::Command;
+use crate::hosts::HostsOrder;
+use crate::logging::LogFormat;
+use crate::reload::Workload;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 pub static CONFIG: Lazy<Config> = Lazy::new(|| {Config::parse()});
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(
         long,
         default_value_t = false)]
@@ -17,6 +31,23 @@ pub struct Config {
         default_value_t = false)]
     pub debug: bool,
 
+    // Format of the log lines
+    #[arg(
+        long,
+        value_name = "LOG_FORMAT",
+        env = "LOG_FORMAT",
+        value_enum,
+        default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    // Per-module log levels in the RUST_LOG syntax, e.g. `info,kube=warn`,
+    // overrides --debug
+    #[arg(
+        long,
+        value_name = "RUST_LOG",
+        env = "RUST_LOG")]
+    pub log_filter: Option<String>,
+
     #[arg(
         long,
         value_name = "HOST_CM_NAME",
@@ -51,14 +82,232 @@ pub struct Config {
         default_value_t = String::from("0.0.0.0:8080"))]
     pub health_listen_addr: String,
 
+    // Kubeconfig file, defaults to the in-cluster configuration or the default kubeconfig.
+    // No env binding: kube reads the KUBECONFIG variable itself, with its path list.
+    #[arg(
+        long,
+        value_name = "KUBECONFIG")]
+    pub kubeconfig: Option<PathBuf>,
+
+    // Kubeconfig context, defaults to the current context
+    #[arg(
+        long,
+        value_name = "KUBE_CONTEXT",
+        env = "KUBE_CONTEXT")]
+    pub kube_context: Option<String>,
+
+    // Timeout in seconds to connect to the API server
+    #[arg(
+        long,
+        value_name = "KUBE_CONNECT_TIMEOUT",
+        env = "KUBE_CONNECT_TIMEOUT",
+        default_value_t = 10)]
+    pub kube_connect_timeout: u64,
+
+    // Timeout in seconds to read a response from the API server, longer than the watch timeout (290s)
+    #[arg(
+        long,
+        value_name = "KUBE_READ_TIMEOUT",
+        env = "KUBE_READ_TIMEOUT",
+        default_value_t = 295)]
+    pub kube_read_timeout: u64,
+
+    // User-Agent sent to the API server
+    #[arg(
+        long,
+        value_name = "KUBE_USER_AGENT",
+        env = "KUBE_USER_AGENT",
+        default_value_t = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")))]
+    pub kube_user_agent: String,
+
+    // Serve reads from an in-memory copy of the ConfigMap kept up to date by a watch
+    #[arg(
+        long,
+        value_name = "WATCH_CACHE",
+        env = "WATCH_CACHE",
+        default_value_t = true,
+        action = clap::ArgAction::Set)]
+    pub watch_cache: bool,
+
+    // Field manager owning the hosts key of the ConfigMap with server-side apply
+    #[arg(
+        long,
+        value_name = "FIELD_MANAGER",
+        env = "FIELD_MANAGER",
+        default_value_t = String::from("external-dns-host-webhook"))]
+    pub field_manager: String,
+
+    // Take the ownership of the hosts key when another field manager owns it
+    #[arg(
+        long,
+        value_name = "FORCE_CONFLICTS",
+        env = "FORCE_CONFLICTS",
+        default_value_t = true,
+        action = clap::ArgAction::Set)]
+    pub force_conflicts: bool,
+
+    // Number of retries when the ConfigMap is modified between a read and a write
+    #[arg(
+        long,
+        value_name = "WRITE_RETRIES",
+        env = "WRITE_RETRIES",
+        default_value_t = 5)]
+    pub write_retries: u32,
+
+    // Initial delay between write retries, doubled on each retry
+    #[arg(
+        long,
+        value_name = "WRITE_RETRY_BACKOFF_MS",
+        env = "WRITE_RETRY_BACKOFF_MS",
+        default_value_t = 100)]
+    pub write_retry_backoff_ms: u64,
+
+    // Changes received within this window are written in one ConfigMap update
+    #[arg(
+        long,
+        value_name = "WRITE_COALESCE_MS",
+        env = "WRITE_COALESCE_MS",
+        default_value_t = 50)]
+    pub write_coalesce_ms: u64,
+
+    // Workloads rolled after each change of the hosts, as `[namespace/]kind/name`
+    // with kind deployment or daemonset, comma separated
+    #[arg(
+        long,
+        value_name = "RESTART_WORKLOADS",
+        env = "RESTART_WORKLOADS",
+        value_delimiter = ',')]
+    pub restart_workloads: Vec<Workload>,
+
+    // Command run with `sh -c` after each change of the hosts, repeatable.
+    // The change is in HOSTS_* environment variables.
+    #[arg(
+        long,
+        value_name = "POST_WRITE_EXEC",
+        env = "POST_WRITE_EXEC")]
+    pub post_write_exec: Vec<String>,
+
+    // URL receiving a POST with the change as JSON after each change of the hosts, comma separated
+    #[arg(
+        long,
+        value_name = "POST_WRITE_URL",
+        env = "POST_WRITE_URL",
+        value_delimiter = ',')]
+    pub post_write_url: Vec<String>,
+
+    // Timeout in seconds of each post-write command or callback
+    #[arg(
+        long,
+        value_name = "POST_WRITE_TIMEOUT_SECS",
+        env = "POST_WRITE_TIMEOUT_SECS",
+        default_value_t = 10)]
+    pub post_write_timeout_secs: u64,
+
+    // Number of retries of a failed post-write command or callback
+    #[arg(
+        long,
+        value_name = "POST_WRITE_RETRIES",
+        env = "POST_WRITE_RETRIES",
+        default_value_t = 2)]
+    pub post_write_retries: u32,
+
+    // Order of the names in the hosts ConfigMap
+    #[arg(
+        long,
+        value_name = "HOSTS_ORDER",
+        env = "HOSTS_ORDER",
+        value_enum,
+        default_value_t = HostsOrder::Name)]
+    pub hosts_order: HostsOrder,
+
+    // Rendered hosts size in bytes above which the records are split across shard
+    // ConfigMaps (ConfigMaps are capped at 1 MiB), 0 never shards
+    #[arg(
+        long,
+        value_name = "SHARD_THRESHOLD_BYTES",
+        env = "SHARD_THRESHOLD_BYTES",
+        default_value_t = 900 * 1024)]
+    pub shard_threshold_bytes: usize,
+
+    // Number of hosts revisions kept for rollback, 0 disables the history
+    #[arg(
+        long,
+        value_name = "HISTORY_LIMIT",
+        env = "HISTORY_LIMIT",
+        default_value_t = 10)]
+    pub history_limit: usize,
+
+    // Prefix of the ConfigMaps holding the hosts revisions, one or more per revision,
+    // defaults to <HOST_CONFIGMAP_NAME>-history
+    #[arg(
+        long,
+        value_name = "HISTORY_CONFIGMAP_NAME",
+        env = "HISTORY_CONFIGMAP_NAME")]
+    pub history_configmap_name: Option<String>,
+
+    // Size in bytes of the hosts content held by one revision ConfigMap, larger
+    // revisions are split across several. Kept under the 1 MiB ConfigMap limit,
+    // with room for the metadata, whether the records are sharded or not.
+    #[arg(
+        long,
+        value_name = "HISTORY_PART_BYTES",
+        env = "HISTORY_PART_BYTES",
+        value_parser = clap::value_parser!(u64).range(1..=960 * 1024),
+        default_value_t = 900 * 1024)]
+    pub history_part_bytes: u64,
+
+    // Publish Kubernetes Events on the hosts ConfigMap for each write
+    #[arg(
+        long,
+        value_name = "EVENTS",
+        env = "EVENTS",
+        action = clap::ArgAction::Set,
+        default_value_t = true)]
+    pub events: bool,
+
+    // Shortest delay between two updates of a repeated Event, repeats in between are only counted
+    #[arg(
+        long,
+        value_name = "EVENT_INTERVAL_SECS",
+        env = "EVENT_INTERVAL_SECS",
+        default_value_t = 60)]
+    pub event_interval_secs: u64,
+
+    // Listen address serving the OpenAPI document, defaults to the health listener
+    #[arg(
+        long,
+        value_name = "OPENAPI_LISTEN_ADDR",
+        env = "OPENAPI_LISTEN_ADDR")]
+    pub openapi_listen_addr: Option<String>,
+
+    // Listen address serving the admin routes, the admin API isn't served without
+    // it: the health listener is reachable from the whole cluster
+    #[arg(
+        long,
+        value_name = "ADMIN_LISTEN_ADDR",
+        env = "ADMIN_LISTEN_ADDR")]
+    pub admin_listen_addr: Option<String>,
+
+    // Bearer token required on the admin routes, the admin API is disabled without it
+    #[arg(
+        long,
+        value_name = "ADMIN_TOKEN",
+        env = "ADMIN_TOKEN",
+        hide_env_values = true)]
+    pub admin_token: Option<String>,
+
     #[command(flatten)]
     pub domain_filter: DomainFilter,
+
+    #[command(flatten)]
+    pub change_policy: ChangePolicy,
 }
 
-#[derive(Serialize, Deserialize, Parser, Debug, Clone)]
+#[derive(Serialize, Deserialize, ToSchema, Parser, Debug, Clone)]
 #[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+#[salvo(schema(rename_all = "camelCase"))]
 pub struct DomainFilter {
-    // Filters define what domains to match
+    /// Filters define what domains to match
     #[arg(
         long,
         value_delimiter = ',',
@@ -67,7 +316,7 @@ pub struct DomainFilter {
         default_value = ".local")]
     pub filters: Vec<String>,
     
-    // exclude define what domains not to match
+    /// exclude define what domains not to match
     #[arg(
         long,
         value_delimiter = ',',
@@ -76,19 +325,98 @@ pub struct DomainFilter {
         default_value = "")]
     pub exclude: Vec<String>,
     
-    // regex defines a regular expression to match the domains
+    /// regex defines a regular expression to match the domains
     #[arg(
         long,
         value_name = "DOMAINS_REGEX",
         env = "DOMAINS_REGEX",
         default_value = "")]
-    pub regex: String,
+    #[salvo(schema(value_type = String))]
+    pub regex: FilterRegex,
 
-    // regexExclusion defines a regular expression to exclude the domains matched
+    /// regexExclusion defines a regular expression to exclude the domains matched
     #[arg(
         long,
         value_name = "DOMAINS_REGEX_EXCUDE",
         env = "DOMAINS_REGEX_EXCUDE",
         default_value = "")]
-    pub regex_exclusion: String,
+    #[salvo(schema(value_type = String))]
+    pub regex_exclusion: FilterRegex,
+}
+
+// Regular expression of the domain filter, compiled when the arguments are parsed
+// so that an invalid pattern stops the provider at startup. Empty means unset.
+#[derive(Debug, Clone, Default)]
+pub struct FilterRegex(Option<Regex>);
+
+impl FilterRegex {
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_ref().map_or("", Regex::as_str)
+    }
+}
+
+impl FromStr for FilterRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Ok(FilterRegex(None)),
+            pattern => Regex::new(pattern).map(|re| FilterRegex(Some(re))),
+        }
+    }
+}
+
+impl fmt::Display for FilterRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Sent to external-dns as the pattern string
+impl Serialize for FilterRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl DomainFilter {
+    // Check a DNS name against the filter, following external-dns DomainFilter semantics:
+    // regex and regex_exclusion take precedence over filters and exclude when set.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if let Some(exclusion) = &self.regex_exclusion.0 {
+            return !exclusion.is_match(&name);
+        }
+        if let Some(regex) = &self.regex.0 {
+            return regex.is_match(&name);
+        }
+        match_filters(&self.filters, &name, true) && !match_filters(&self.exclude, &name, false)
+    }
+}
+
+fn match_filters(filters: &[String], name: &str, empty_value: bool) -> bool {
+    let filters: Vec<String> = filters.iter()
+        .map(|f| f.trim().trim_end_matches('.').to_lowercase())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if filters.is_empty() {
+        return empty_value;
+    }
+    filters.iter().any(|filter| {
+        if filter.starts_with('.') {
+            name.ends_with(filter.as_str())
+        } else {
+            name == filter || name.ends_with(&format!(".{filter}"))
+        }
+    })
 }
@@ -0,0 +1,141 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::RwLock;
+use chrono::{SecondsFormat, Utc};
+use kube::Client;
+use once_cell::sync::Lazy;
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::changes::{apply_changes, without_owned, ChangePolicy, ChangeReport, SkippedChange};
+use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use crate::hosts::{current_host, format_records, HostRecords, HostsSnapshot};
+use crate::records::Changes;
+
+// Diff labels of the current and planned hosts content
+pub const PLAN_LABELS: (&str, &str) = ("current", "planned");
+
+// Last change set evaluated in dry-run, served by the admin listener
+pub static LAST_DRY_RUN: Lazy<RwLock<Option<DryRun>>> = Lazy::new(|| RwLock::new(None));
+
+// Outcome of a change set that wasn't written
+#[derive(Serialize, ToSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRun {
+    /// Evaluation time, RFC 3339
+    pub at: String,
+    /// ConfigMap version the changes were evaluated against
+    pub resource_version: Option<String>,
+    /// Changes that would have been written
+    pub changes: ChangeReport,
+    /// Unified diff of the hosts content, empty when nothing changes
+    pub diff: String,
+}
+
+// Unified diff between two versions of the records, rendered canonically
+pub fn hosts_diff(before: &HostRecords, after: &HostRecords, labels: (&str, &str)) -> String {
+    let (before, after) = (format_records(before), format_records(after));
+    TextDiff::from_lines(&before, &after)
+        .unified_diff()
+        .header(labels.0, labels.1)
+        .to_string()
+}
+
+// Targets of one name before and after a change set, sorted
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct PlannedName {
+    pub name: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+// Effect of a change set on the stored names
+#[derive(Serialize, ToSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// False when the change set would be rejected, see `errors`
+    pub valid: bool,
+    /// Reasons why the change set would be rejected
+    pub errors: Vec<String>,
+    /// Names that don't exist yet
+    pub added: Vec<PlannedName>,
+    /// Names whose every target is removed
+    pub removed: Vec<PlannedName>,
+    /// Names whose targets change
+    pub modified: Vec<PlannedName>,
+    /// Changes that would be ignored
+    pub skipped: Vec<SkippedChange>,
+    /// Unified diff of the hosts content
+    pub diff: String,
+}
+
+fn sorted(targets: Option<&HashSet<String>>) -> Vec<String> {
+    let mut targets: Vec<String> = targets.into_iter().flatten().cloned().collect();
+    targets.sort();
+    targets
+}
+
+// Compare the records name by name, sorted by name
+pub fn name_changes(before: &HostRecords, after: &HostRecords) -> (Vec<PlannedName>, Vec<PlannedName>, Vec<PlannedName>) {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let (mut added, mut removed, mut modified) = (Vec::new(), Vec::new(), Vec::new());
+    for name in names {
+        let change = PlannedName { name: name.clone(), before: sorted(before.get(name)), after: sorted(after.get(name)) };
+        if change.before == change.after {
+            continue;
+        }
+        if change.before.is_empty() {
+            added.push(change);
+        } else if change.after.is_empty() {
+            removed.push(change);
+        } else {
+            modified.push(change);
+        }
+    }
+    (added, removed, modified)
+}
+
+// Apply a change set like the writer does, skipping operator-owned names
+fn evaluate(current: &HostsSnapshot, changes: &Changes, policy: &ChangePolicy) -> Result<(HostRecords, ChangeReport)> {
+    let (changes, skipped) = without_owned(changes, &current.owned);
+    let (records, mut report) = apply_changes(&current.records, &changes, policy)?;
+    report.skipped.extend(skipped);
+    Ok((records, report))
+}
+
+// Evaluate a change set on records, rejections are reported in the plan
+pub fn plan_changes(current: &HostsSnapshot, changes: &Changes, policy: &ChangePolicy) -> Plan {
+    match evaluate(current, changes, policy) {
+        Ok((records, report)) => {
+            let (added, removed, modified) = name_changes(&current.records, &records);
+            Plan {
+                valid: true,
+                added,
+                removed,
+                modified,
+                skipped: report.skipped,
+                diff: hosts_diff(&current.records, &records, PLAN_LABELS),
+                ..Default::default()
+            }
+        }
+        Err(e) => {
+            let errors = match &e {
+                Error::Validation { details, .. } => details.clone(),
+                _ => vec![e.to_string()],
+            };
+            Plan { valid: false, errors, ..Default::default() }
+        }
+    }
+}
+
+// Run the read-apply pipeline on the current records without writing them
+pub async fn dry_run(client: &Client, changes: &Changes) -> Result<DryRun> {
+    let snapshot = current_host(client).await?;
+    let (records, report) = evaluate(&snapshot, changes, &CONFIG.change_policy)?;
+    Ok(DryRun {
+        at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        resource_version: snapshot.resource_version,
+        changes: report,
+        diff: hosts_diff(&snapshot.records, &records, PLAN_LABELS),
+    })
+}
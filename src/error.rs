@@ -0,0 +1,192 @@
+use salvo::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use salvo::http::ParseError;
+use salvo::oapi::{Components, EndpointOutRegister, Operation, ToSchema};
+use salvo::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{error, warn};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // Failure while talking to the Kubernetes API server
+    #[error("kubernetes API error: {0}")]
+    Kube(#[from] kube::Error),
+
+    // Invalid kube client configuration
+    #[error("kubernetes client configuration error: {0}")]
+    KubeConfig(String),
+
+    // Request body that can't be decoded
+    #[error("invalid request body: {0}")]
+    Parse(#[from] ParseError),
+
+    // Request content that is well formed but can't be applied
+    #[error("validation failed: {message}")]
+    Validation { message: String, details: Vec<String> },
+
+    // Concurrent modification of the stored records
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    // Names outside of the configured domain filter
+    #[error("domain filter violation: {}", names.join(","))]
+    Filter { names: Vec<String> },
+
+    // Accept header excluding the webhook media type
+    #[error("not acceptable, supported media type is {0}")]
+    NotAcceptable(&'static str),
+
+    // Request body not sent as the webhook media type
+    #[error("unsupported media type, expected {0}")]
+    UnsupportedMediaType(&'static str),
+
+    // Admin request without a valid token
+    #[error("unauthorized: {0}")]
+    Unauthorized(&'static str),
+
+    // Resource of the provider that doesn't exist
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    // Error of a coalesced write, reported to every caller of the batch
+    #[error(transparent)]
+    Shared(Arc<Error>),
+
+    // Internal task that stopped, e.g. the writer
+    #[error("service unavailable: {0}")]
+    Unavailable(String),
+
+    // Local file that can't be read or written
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    // Response serialization failure
+    #[error("serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// JSON body returned for every error
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ErrorBody {
+    /// Stable machine readable error code
+    pub code: &'static str,
+    /// Human readable message
+    pub message: String,
+    /// Additional context, e.g. offending names or API server reason
+    pub details: Vec<String>,
+}
+
+impl Error {
+    pub fn validation(message: impl Into<String>, details: Vec<String>) -> Self {
+        Error::Validation { message: message.into(), details }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Kube(e) if kube_status(e) == Some(409) => "conflict",
+            Error::Kube(_) => "kube_error",
+            Error::KubeConfig(_) => "kube_config_error",
+            Error::Parse(_) => "parse_error",
+            Error::Validation { .. } => "validation_failed",
+            Error::Conflict(_) => "conflict",
+            Error::Filter { .. } => "filter_violation",
+            Error::NotAcceptable(_) => "not_acceptable",
+            Error::UnsupportedMediaType(_) => "unsupported_media_type",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::NotFound(_) => "not_found",
+            Error::Shared(e) => e.code(),
+            Error::Unavailable(_) => "unavailable",
+            Error::Io(_) => "io_error",
+            Error::Json(_) => "internal_error",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Kube(e) => match kube_status(e) {
+                Some(409) => StatusCode::CONFLICT,
+                Some(404) => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+            Error::KubeConfig(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Parse(_) => StatusCode::BAD_REQUEST,
+            Error::Validation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Filter { .. } => StatusCode::FORBIDDEN,
+            Error::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Shared(e) => e.status_code(),
+            Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn details(&self) -> Vec<String> {
+        match self {
+            Error::Kube(kube::Error::Api(e)) => vec![format!("reason={}", e.reason), format!("status={}", e.code)],
+            Error::Validation { details, .. } => details.clone(),
+            Error::Filter { names } => names.clone(),
+            Error::Shared(e) => e.details(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+        }
+    }
+}
+
+// HTTP status returned by the API server, if any
+fn kube_status(e: &kube::Error) -> Option<u16> {
+    match e {
+        kube::Error::Api(e) => Some(e.code),
+        _ => None,
+    }
+}
+
+impl Scribe for Error {
+    fn render(self, res: &mut Response) {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("{self}");
+        } else {
+            warn!("{self}");
+        }
+        res.status_code(status);
+        if status == StatusCode::UNAUTHORIZED {
+            res.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        res.render(Json(self.body()));
+    }
+}
+
+impl EndpointOutRegister for Error {
+    fn register(components: &mut Components, operation: &mut Operation) {
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+            StatusCode::NOT_ACCEPTABLE,
+            StatusCode::CONFLICT,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            let response = salvo::oapi::Response::new(status.canonical_reason().unwrap_or_default())
+                .add_content("application/json", ErrorBody::to_schema(components));
+            operation.responses.insert(status.as_str(), response);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::{Api, Patch, PatchParams, PostParams};
+use kube::Client;
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+use crate::config::CONFIG;
+use crate::hosts::host_namespace;
+
+// Repeated events within this window are counted on the same Event object
+const AGGREGATION_WINDOW: Duration = Duration::from_secs(10 * 60);
+// Events of one object published at once at most, then one per OBJECT_REFILL
+const OBJECT_BURST: f64 = 25.0;
+const OBJECT_REFILL: Duration = Duration::from_secs(5 * 60);
+// Events waiting for the recorder task, newer ones are dropped
+const QUEUE_SIZE: usize = 256;
+// Longest message the API server accepts for an Event
+const MAX_MESSAGE_LEN: usize = 1024;
+const COMPONENT: &str = env!("CARGO_PKG_NAME");
+
+pub const NORMAL: &str = "Normal";
+pub const WARNING: &str = "Warning";
+
+// What to do with an observed event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventAction {
+    // First occurrence, create an Event with the occurrences counted so far
+    Create { count: i32 },
+    // Repeated occurrence, update the count of the existing Event
+    Update { name: String, count: i32 },
+    // Repeated too soon or over the rate limit of the object, only counted until
+    // the next update or flush
+    Suppress,
+}
+
+// Counted occurrences of an event not published yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEvent {
+    pub type_: String,
+    pub reason: String,
+    pub object: String,
+    // Message of the latest occurrence
+    pub message: String,
+    pub action: EventAction,
+}
+
+// Events are aggregated by type and reason on an involved object, the message of
+// a write changes with its counts
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    type_: String,
+    reason: String,
+    object: String,
+}
+
+struct Seen {
+    name: Option<String>,
+    count: i32,
+    // Count of the published Event
+    sent: i32,
+    message: String,
+    first: Instant,
+    last_sent: Instant,
+}
+
+// Token bucket limiting the Events of one object, like the spam filter of client-go
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn take(&mut self, now: Instant) -> bool {
+        let refilled = now.duration_since(self.updated).as_secs_f64() / OBJECT_REFILL.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(OBJECT_BURST);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// Aggregate repeated events and limit how often they reach the API server,
+// like the event correlator of client-go
+pub struct EventAggregator {
+    interval: Duration,
+    seen: HashMap<EventKey, Seen>,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl EventAggregator {
+    // `interval` is the shortest delay between two updates of the same Event
+    pub fn new(interval: Duration) -> Self {
+        EventAggregator { interval, seen: HashMap::new(), buckets: HashMap::new() }
+    }
+
+    pub fn observe(&mut self, type_: &str, reason: &str, object: &str, message: &str, now: Instant) -> EventAction {
+        // expired events are forgotten once their occurrences are published
+        self.seen.retain(|_, seen| now.duration_since(seen.first) < AGGREGATION_WINDOW || seen.count > seen.sent);
+        let bucket = self.buckets.entry(object.into())
+            .or_insert_with(|| TokenBucket { tokens: OBJECT_BURST, updated: now });
+        let key = EventKey { type_: type_.into(), reason: reason.into(), object: object.into() };
+        let Some(seen) = self.seen.get_mut(&key) else {
+            let allowed = bucket.take(now);
+            let sent = if allowed { 1 } else { 0 };
+            self.seen.insert(key, Seen { name: None, count: 1, sent, message: message.into(), first: now, last_sent: now });
+            return if allowed { EventAction::Create { count: 1 } } else { EventAction::Suppress };
+        };
+        seen.count += 1;
+        seen.message = message.into();
+        if now.duration_since(seen.last_sent) < self.interval || !bucket.take(now) {
+            return EventAction::Suppress;
+        }
+        seen.last_sent = now;
+        seen.sent = seen.count;
+        match &seen.name {
+            Some(name) => EventAction::Update { name: name.clone(), count: seen.count },
+            None => EventAction::Create { count: seen.count },
+        }
+    }
+
+    // Remember the Event object created for an event
+    pub fn created(&mut self, type_: &str, reason: &str, object: &str, name: String) {
+        let key = EventKey { type_: type_.into(), reason: reason.into(), object: object.into() };
+        if let Some(seen) = self.seen.get_mut(&key) {
+            seen.name = Some(name);
+        }
+    }
+
+    // Occurrences suppressed since the last publication of each event, to publish
+    // on a timer and at shutdown. They bypass the rate limit, there is one at most
+    // per event and flush.
+    pub fn flush(&mut self, now: Instant) -> Vec<PendingEvent> {
+        let mut pending = Vec::new();
+        for (key, seen) in self.seen.iter_mut().filter(|(_, seen)| seen.count > seen.sent) {
+            seen.sent = seen.count;
+            seen.last_sent = now;
+            let action = match &seen.name {
+                Some(name) => EventAction::Update { name: name.clone(), count: seen.count },
+                None => EventAction::Create { count: seen.count },
+            };
+            pending.push(PendingEvent {
+                type_: key.type_.clone(),
+                reason: key.reason.clone(),
+                object: key.object.clone(),
+                message: seen.message.clone(),
+                action,
+            });
+        }
+        pending
+    }
+}
+
+enum Command {
+    Record { type_: &'static str, reason: &'static str, message: String },
+    Close(oneshot::Sender<()>),
+}
+
+// Handle to the task publishing Events on the hosts ConfigMap
+#[derive(Clone)]
+pub struct EventRecorder {
+    tx: mpsc::Sender<Command>,
+}
+
+impl EventRecorder {
+    // Start the task publishing the Events, it flushes the suppressed occurrences
+    // every interval
+    pub fn spawn(client: &Client) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(run_recorder(Publisher::new(client), rx));
+        EventRecorder { tx }
+    }
+
+    // Publish in the background, failures are only logged
+    pub fn emit(&self, type_: &'static str, reason: &'static str, message: String) {
+        if self.tx.try_send(Command::Record { type_, reason, message }).is_err() {
+            warn!("event {reason} dropped, the event recorder is stopped or behind");
+        }
+    }
+
+    // Publish the suppressed occurrences and wait for the Events emitted before
+    pub async fn close(&self) {
+        let (reply, done) = oneshot::channel();
+        if self.tx.send(Command::Close(reply)).await.is_ok() {
+            _ = done.await;
+        }
+    }
+}
+
+async fn run_recorder(publisher: Publisher, mut rx: mpsc::Receiver<Command>) {
+    let interval = Duration::from_secs(CONFIG.event_interval_secs).max(Duration::from_secs(1));
+    let mut aggregator = EventAggregator::new(interval);
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(Command::Record { type_, reason, message }) => {
+                    let message = truncate(&message);
+                    let object = publisher.object();
+                    match aggregator.observe(type_, reason, &object, message, Instant::now()) {
+                        EventAction::Suppress => debug!("event {reason} suppressed: {message}"),
+                        action => publisher.publish(&mut aggregator, type_, reason, message, action).await,
+                    }
+                }
+                Some(Command::Close(reply)) => {
+                    flush(&publisher, &mut aggregator).await;
+                    _ = reply.send(());
+                }
+                None => {
+                    flush(&publisher, &mut aggregator).await;
+                    return;
+                }
+            },
+            _ = ticks.tick() => flush(&publisher, &mut aggregator).await,
+        }
+    }
+}
+
+async fn flush(publisher: &Publisher, aggregator: &mut EventAggregator) {
+    for pending in aggregator.flush(Instant::now()) {
+        debug!("event {} repeated {:?}", pending.reason, pending.action);
+        publisher.publish(aggregator, &pending.type_, &pending.reason, &pending.message, pending.action).await;
+    }
+}
+
+// Create and update the Events of the hosts ConfigMap
+struct Publisher {
+    events: Api<Event>,
+    reference: ObjectReference,
+}
+
+impl Publisher {
+    fn new(client: &Client) -> Self {
+        let namespace = host_namespace(client).to_string();
+        Publisher {
+            events: Api::namespaced(client.clone(), &namespace),
+            reference: ObjectReference {
+                api_version: Some("v1".into()),
+                kind: Some("ConfigMap".into()),
+                name: Some(CONFIG.host_configmap_name.clone()),
+                namespace: Some(namespace),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Key of the involved object in the aggregator
+    fn object(&self) -> String {
+        format!("{}/{}", self.reference.namespace.as_deref().unwrap_or_default(), self.reference.name.as_deref().unwrap_or_default())
+    }
+
+    async fn publish(&self, aggregator: &mut EventAggregator, type_: &str, reason: &str, message: &str, action: EventAction) {
+        if let Err(e) = self.apply(aggregator, type_, reason, message, action).await {
+            warn!("failed to publish event {reason}: {e}");
+        }
+    }
+
+    async fn apply(&self, aggregator: &mut EventAggregator, type_: &str, reason: &str, message: &str, action: EventAction) -> Result<(), kube::Error> {
+        let count = match action {
+            EventAction::Suppress => return Ok(()),
+            EventAction::Update { name, count } => {
+                let patch = json!({ "count": count, "message": message, "lastTimestamp": Time(Utc::now()) });
+                match self.events.patch(&name, &PatchParams::default(), &Patch::Merge(&patch)).await {
+                    Ok(_) => return Ok(()),
+                    // expired, created again below
+                    Err(kube::Error::Api(e)) if e.code == 404 => count,
+                    Err(e) => return Err(e),
+                }
+            }
+            EventAction::Create { count } => count,
+        };
+        let event = self.event(type_, reason, message, count);
+        let created = self.events.create(&PostParams::default(), &event).await?;
+        if let Some(name) = created.metadata.name {
+            aggregator.created(type_, reason, &self.object(), name);
+        }
+        Ok(())
+    }
+
+    fn event(&self, type_: &str, reason: &str, message: &str, count: i32) -> Event {
+        let now = Utc::now();
+        Event {
+            metadata: kube::api::ObjectMeta {
+                name: Some(format!("{}.{:x}", CONFIG.host_configmap_name, now.timestamp_nanos_opt().unwrap_or_default())),
+                namespace: self.reference.namespace.clone(),
+                ..Default::default()
+            },
+            involved_object: self.reference.clone(),
+            type_: Some(type_.into()),
+            reason: Some(reason.into()),
+            message: Some(message.into()),
+            action: Some("Write".into()),
+            count: Some(count),
+            first_timestamp: Some(Time(now)),
+            last_timestamp: Some(Time(now)),
+            reporting_component: Some(COMPONENT.into()),
+            reporting_instance: std::env::var("HOSTNAME").ok(),
+            source: Some(EventSource { component: Some(COMPONENT.into()), host: None }),
+            ..Default::default()
+        }
+    }
+}
+
+fn truncate(message: &str) -> &str {
+    let mut end = message.len().min(MAX_MESSAGE_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    &message[..end]
+}
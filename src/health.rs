@@ -1,10 +1,291 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use futures::future::try_join_all;
+use k8s_openapi::api::authorization::v1::{ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec};
+use kube::api::{Api, PostParams};
+use kube::Client;
+use once_cell::sync::Lazy;
+use salvo::oapi::extract::QueryParam;
 use salvo::prelude::*;
-use tracing::debug;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
 
-#[handler]
-pub async fn get_healthz(res: &mut Response) {
+use crate::cache::CACHE;
+use crate::client::obtain_client;
+use crate::config::CONFIG;
+use crate::hosts::host_namespace;
+use crate::reload::{Workload, WorkloadKind};
+use crate::writer::obtain_writer;
+
+// Delay between two heartbeats of the runtime
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// Heartbeat older than this means the runtime is wedged
+pub const MAX_HEARTBEAT_LAG: Duration = Duration::from_secs(10);
+// Longest wait for the API server in a readiness check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+// How long a successful RBAC check is reused by the readiness probes
+const RBAC_CHECK_TTL: Duration = Duration::from_secs(60);
+
+// Time of the last heartbeat, in milliseconds since the epoch
+static LAST_HEARTBEAT: AtomicI64 = AtomicI64::new(0);
+// Last successful RBAC check and when it was made
+static RBAC_RESULT: Lazy<Mutex<Option<(Instant, String)>>> = Lazy::new(|| Mutex::new(None));
+
+// Result of one check of a probe
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    /// Why the check failed, or what it found
+    pub message: String,
+}
+
+impl Check {
+    pub fn new(name: &str, result: Result<String, String>) -> Self {
+        let ok = result.is_ok();
+        let message = result.unwrap_or_else(|e| e);
+        Check { name: name.into(), ok, message }
+    }
+}
+
+// Verbose answer of a probe
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// `ok` when every check passed, `failed` otherwise
+    pub status: String,
+    pub checks: Vec<Check>,
+}
+
+impl ProbeReport {
+    pub fn new(checks: Vec<Check>) -> Self {
+        let status = if checks.iter().all(|c| c.ok) { "ok" } else { "failed" };
+        ProbeReport { status: status.into(), checks }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+}
+
+// Runtime check from the age of the last heartbeat
+pub fn heartbeat_check(lag: Duration) -> Check {
+    let result = if lag <= MAX_HEARTBEAT_LAG {
+        Ok(format!("last heartbeat {}ms ago", lag.as_millis()))
+    } else {
+        Err(format!("no heartbeat for {}s, the runtime is wedged", lag.as_secs()))
+    };
+    Check::new("runtime", result)
+}
+
+// Tick the heartbeat checked by /livez, never returns
+pub async fn run_heartbeat() {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        LAST_HEARTBEAT.store(Utc::now().timestamp_millis(), Ordering::Release);
+    }
+}
+
+fn heartbeat_lag() -> Duration {
+    let last = LAST_HEARTBEAT.load(Ordering::Acquire);
+    Duration::from_millis(Utc::now().timestamp_millis().saturating_sub(last).max(0) as u64)
+}
+
+async fn with_timeout(check: impl Future<Output = Result<String, String>>) -> Result<String, String> {
+    tokio::time::timeout(CHECK_TIMEOUT, check).await
+        .unwrap_or_else(|_| Err(format!("no answer from the API server within {}s", CHECK_TIMEOUT.as_secs())))
+}
+
+async fn kube_check(client: &Client) -> Result<String, String> {
+    client.apiserver_version().await
+        .map(|version| format!("API server {}", version.git_version))
+        .map_err(|e| format!("API server unreachable: {e}"))
+}
+
+// Verbs the provider needs on a resource, on one object or on any when `name` is None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub verbs: Vec<&'static str>,
+    pub group: &'static str,
+    pub resource: &'static str,
+    pub namespace: String,
+    pub name: Option<String>,
+}
+
+impl Access {
+    fn new(verbs: &[&'static str], group: &'static str, resource: &'static str, namespace: &str, name: Option<&str>) -> Self {
+        Access { verbs: verbs.to_vec(), group, resource, namespace: namespace.into(), name: name.map(String::from) }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {} {}/{}", self.verbs.join(", "), self.resource, self.namespace, self.name.as_deref().unwrap_or("*"))
+    }
+}
+
+// Access the provider needs with the given configuration:
+// - the hosts ConfigMap is read and written with server-side apply, which creates
+//   it when missing, and listed and watched by the cache
+// - shard ConfigMaps are named after their content, written, read and deleted
+// - revision ConfigMaps are listed, written, read and deleted
+// - restarted workloads are patched
+pub fn required_access(namespace: &str, configmap: &str, watch_cache: bool, sharded: bool, history: bool, events: bool, workloads: &[Workload]) -> Vec<Access> {
+    let mut verbs = vec!["get", "patch", "create"];
+    if watch_cache {
+        verbs.extend(["list", "watch"]);
+    }
+    let mut access = vec![Access::new(&verbs, "", "configmaps", namespace, Some(configmap))];
+    match (sharded, history) {
+        (_, true) => access.push(Access::new(&["get", "patch", "create", "delete", "list"], "", "configmaps", namespace, None)),
+        (true, false) => access.push(Access::new(&["get", "patch", "create", "delete"], "", "configmaps", namespace, None)),
+        (false, false) => {}
+    }
+    if events {
+        access.push(Access::new(&["create", "patch"], "", "events", namespace, None));
+    }
+    for workload in workloads {
+        let resource = match workload.kind {
+            WorkloadKind::Deployment => "deployments",
+            WorkloadKind::DaemonSet => "daemonsets",
+        };
+        let namespace = workload.namespace.as_deref().unwrap_or(namespace);
+        access.push(Access::new(&["patch"], "apps", resource, namespace, Some(&workload.name)));
+    }
+    access
+}
+
+async fn rbac_check(client: &Client) -> Result<String, String> {
+    let reviews: Api<SelfSubjectAccessReview> = Api::all(client.clone());
+    let access = required_access(host_namespace(client), &CONFIG.host_configmap_name, CONFIG.watch_cache,
+        CONFIG.shard_threshold_bytes > 0, CONFIG.history_limit > 0, CONFIG.events, &CONFIG.restart_workloads);
+    let checks: Vec<(&Access, &str)> = access.iter()
+        .flat_map(|access| access.verbs.iter().map(move |verb| (access, *verb)))
+        .collect();
+    let mut reviewed = Vec::new();
+    for (access, verb) in &checks {
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    namespace: Some(access.namespace.clone()),
+                    verb: Some(verb.to_string()),
+                    group: Some(access.group.into()),
+                    resource: Some(access.resource.into()),
+                    name: access.name.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let reviews = reviews.clone();
+        reviewed.push(async move { reviews.create(&PostParams::default(), &review).await });
+    }
+    let reviewed = try_join_all(reviewed).await.map_err(|e| format!("access review failed: {e}"))?;
+    let denied: Vec<String> = checks.iter().zip(reviewed)
+        .filter(|(_, review)| !review.status.as_ref().is_some_and(|status| status.allowed))
+        .map(|((access, verb), _)| Access { verbs: vec![verb], ..(*access).clone() }.to_string())
+        .collect();
+    if denied.is_empty() {
+        Ok(format!("allowed {}", access.iter().map(Access::to_string).collect::<Vec<_>>().join("; ")))
+    } else {
+        Err(format!("forbidden {}", denied.join("; ")))
+    }
+}
+
+// RBAC check reused for RBAC_CHECK_TTL once successful, failures are checked again
+// on the next probe. Concurrent probes wait for the same check.
+async fn cached_rbac_check(client: &Client) -> Result<String, String> {
+    let mut last = RBAC_RESULT.lock().await;
+    if let Some((at, message)) = last.as_ref() {
+        if at.elapsed() < RBAC_CHECK_TTL {
+            return Ok(message.clone());
+        }
+    }
+    let result = with_timeout(rbac_check(client)).await;
+    *last = result.as_ref().ok().map(|message| (Instant::now(), message.clone()));
+    result
+}
+
+fn cache_check() -> Check {
+    let result = match (CONFIG.watch_cache, CACHE.is_synced()) {
+        (false, _) => Ok("watch cache disabled".into()),
+        (true, true) => Ok("synced".into()),
+        (true, false) => Err("hosts cache not synced yet".into()),
+    };
+    Check::new("cache", result)
+}
+
+fn render_report(res: &mut Response, report: ProbeReport, verbose: bool) {
+    if !report.is_ok() {
+        res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        for check in report.checks.iter().filter(|c| !c.ok) {
+            warn!("{} check failed: {}", check.name, check.message);
+        }
+    } else {
+        res.status_code(StatusCode::OK);
+    }
+    match (verbose, report.is_ok()) {
+        (true, _) => res.render(Json(report)),
+        (false, true) => res.render(Text::Plain("Ok!")),
+        (false, false) => {
+            let failed: Vec<String> = report.checks.iter().filter(|c| !c.ok).map(|c| format!("{}: {}", c.name, c.message)).collect();
+            res.render(Text::Plain(failed.join("\n")));
+        }
+    }
+}
+
+fn liveness(depot: &Depot) -> ProbeReport {
+    let writer = match obtain_writer(depot).is_running() {
+        true => Ok("running".into()),
+        false => Err("hosts writer stopped".into()),
+    };
+    ProbeReport::new(vec![heartbeat_check(heartbeat_lag()), Check::new("writer", writer)])
+}
+
+/// Liveness of the provider, same checks as /livez
+#[endpoint(
+    tags("health"),
+    responses(
+        (status_code = 200, description = "Provider is running", body = ProbeReport),
+        (status_code = 503, description = "Provider is wedged", body = ProbeReport),
+    ),
+)]
+pub async fn get_healthz(verbose: QueryParam<bool, false>, depot: &mut Depot, res: &mut Response) {
     debug!("get_health");
-    res.render(Text::Plain("Ok!"));
-    res.status_code(StatusCode::OK);
+    render_report(res, liveness(depot), verbose.into_inner().unwrap_or_default());
 }
 
+/// Liveness of the provider: the runtime runs tasks and the hosts writer is alive
+#[endpoint(
+    tags("health"),
+    responses(
+        (status_code = 200, description = "Provider is running", body = ProbeReport),
+        (status_code = 503, description = "Provider is wedged", body = ProbeReport),
+    ),
+)]
+pub async fn get_livez(verbose: QueryParam<bool, false>, depot: &mut Depot, res: &mut Response) {
+    debug!("get_live");
+    render_report(res, liveness(depot), verbose.into_inner().unwrap_or_default());
+}
+
+/// Readiness of the provider: the API server is reachable, the hosts ConfigMap
+/// can be read and written, and the hosts cache is synced
+#[endpoint(
+    tags("health"),
+    responses(
+        (status_code = 200, description = "Provider is ready", body = ProbeReport),
+        (status_code = 503, description = "Provider isn't ready", body = ProbeReport),
+    ),
+)]
+pub async fn get_readyz(verbose: QueryParam<bool, false>, depot: &mut Depot, res: &mut Response) {
+    debug!("get_ready");
+    let client = obtain_client(depot);
+    let (kube, rbac) = tokio::join!(with_timeout(kube_check(&client)), cached_rbac_check(&client));
+    let report = ProbeReport::new(vec![Check::new("kubernetes", kube), Check::new("rbac", rbac), cache_check()]);
+    render_report(res, report, verbose.into_inner().unwrap_or_default());
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use chrono::{SecondsFormat, Utc};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams};
+use kube::Client;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::config::CONFIG;
+use crate::dryrun::{hosts_diff, name_changes, PlannedName};
+use crate::error::{Error, Result};
+use crate::hosts::{format_records, host_namespace, parse_hosts, HostRecords};
+
+// Label of the revision ConfigMaps, set to the name of the hosts ConfigMap
+pub const HISTORY_OF_LABEL: &str = "host-webhook-provider/history-of";
+// Label of the revision ConfigMaps, set to the revision number
+pub const REVISION_LABEL: &str = "host-webhook-provider/revision";
+// Annotations of the first ConfigMap of a revision, written last: a revision
+// is complete once they are set
+const AT_ANNOTATION: &str = "host-webhook-provider/revision-at";
+const SUMMARY_ANNOTATION: &str = "host-webhook-provider/revision-summary";
+const LINES_ANNOTATION: &str = "host-webhook-provider/revision-lines";
+const PARTS_ANNOTATION: &str = "host-webhook-provider/revision-parts";
+// Key of the revision ConfigMaps holding their part of the hosts content
+const HOSTS_KEY: &str = "hosts";
+
+// Hosts content written at some point
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Revision {
+    /// Increasing revision number
+    pub revision: u64,
+    /// Write time, RFC 3339
+    pub at: String,
+    /// What the write changed
+    pub summary: String,
+    /// Hosts content written
+    pub hosts: String,
+}
+
+// Revision without its content
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionInfo {
+    pub revision: u64,
+    pub at: String,
+    pub summary: String,
+    /// Number of hosts lines
+    pub lines: usize,
+}
+
+impl RevisionInfo {
+    // Revision described by the first ConfigMap of a revision, None for the other
+    // parts and for revisions not completely written
+    pub fn of(metadata: &ObjectMeta) -> Option<Self> {
+        let revision = metadata.labels.as_ref()?.get(REVISION_LABEL)?.parse().ok()?;
+        let annotations = metadata.annotations.as_ref()?;
+        annotations.get(PARTS_ANNOTATION)?;
+        Some(RevisionInfo {
+            revision,
+            at: annotations.get(AT_ANNOTATION)?.clone(),
+            summary: annotations.get(SUMMARY_ANNOTATION).cloned().unwrap_or_default(),
+            lines: annotations.get(LINES_ANNOTATION).and_then(|lines| lines.parse().ok()).unwrap_or_default(),
+        })
+    }
+}
+
+// Difference between two revisions
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDiff {
+    pub from: u64,
+    pub to: u64,
+    /// Names only in `to`
+    pub added: Vec<PlannedName>,
+    /// Names only in `from`
+    pub removed: Vec<PlannedName>,
+    /// Names whose targets differ
+    pub modified: Vec<PlannedName>,
+    /// Unified diff of the hosts content
+    pub diff: String,
+}
+
+pub fn diff_revisions(from: &Revision, to: &Revision) -> RevisionDiff {
+    let (before, after) = (parse_hosts(&from.hosts), parse_hosts(&to.hosts));
+    let (added, removed, modified) = name_changes(&before, &after);
+    let labels = (format!("revision {}", from.revision), format!("revision {}", to.revision));
+    let diff = hosts_diff(&before, &after, (&labels.0, &labels.1));
+    RevisionDiff { from: from.revision, to: to.revision, added, removed, modified, diff }
+}
+
+// Prefix of the ConfigMaps holding the revisions
+pub fn history_name() -> String {
+    CONFIG.history_configmap_name.clone()
+        .unwrap_or_else(|| format!("{}-history", CONFIG.host_configmap_name))
+}
+
+// Name of one ConfigMap of a revision, `<prefix>-<revision>` for the first one
+pub fn revision_name(prefix: &str, revision: u64, part: usize) -> String {
+    match part {
+        0 => format!("{prefix}-{revision}"),
+        _ => format!("{prefix}-{revision}-{part}"),
+    }
+}
+
+// Split hosts content in parts of `max_bytes` at most, at line boundaries, a
+// longer line is a part alone. A single part when `max_bytes` is 0.
+pub fn split_lines(hosts: &str, max_bytes: usize) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    for line in hosts.split_inclusive('\n') {
+        let part = parts.last_mut().unwrap();
+        if max_bytes > 0 && !part.is_empty() && part.len() + line.len() > max_bytes {
+            parts.push(line.to_string());
+        } else {
+            part.push_str(line);
+        }
+    }
+    parts
+}
+
+// ConfigMaps of a revision of the history of `of`, one per part of the content.
+// The first one describes the revision.
+pub fn revision_configmaps(prefix: &str, of: &str, revision: &Revision, max_bytes: usize) -> Vec<ConfigMap> {
+    let parts = split_lines(&revision.hosts, max_bytes);
+    let count = parts.len();
+    parts.into_iter().enumerate().map(|(part, hosts)| {
+        let annotations = (part == 0).then(|| BTreeMap::from([
+            (AT_ANNOTATION.to_string(), revision.at.clone()),
+            (SUMMARY_ANNOTATION.to_string(), revision.summary.clone()),
+            (LINES_ANNOTATION.to_string(), revision.hosts.lines().count().to_string()),
+            (PARTS_ANNOTATION.to_string(), count.to_string()),
+        ]));
+        ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(revision_name(prefix, revision.revision, part)),
+                labels: Some(BTreeMap::from([
+                    (HISTORY_OF_LABEL.to_string(), of.to_string()),
+                    (REVISION_LABEL.to_string(), revision.revision.to_string()),
+                ])),
+                annotations,
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(HOSTS_KEY.to_string(), hosts)])),
+            ..Default::default()
+        }
+    }).collect()
+}
+
+// Oldest revision kept when only the `limit` latest ones are
+pub fn oldest_kept(mut revisions: Vec<u64>, limit: usize) -> Option<u64> {
+    revisions.sort_unstable_by(|a, b| b.cmp(a));
+    revisions.into_iter().take(limit.max(1)).next_back()
+}
+
+fn history_configmaps(client: &Client) -> Api<ConfigMap> {
+    Api::namespaced(client.clone(), host_namespace(client))
+}
+
+fn history_selector() -> ListParams {
+    ListParams::default().labels(&format!("{HISTORY_OF_LABEL}={}", CONFIG.host_configmap_name))
+}
+
+// Revisions of the history, oldest first
+pub async fn list_revisions(client: &Client) -> Result<Vec<RevisionInfo>> {
+    let configmaps = history_configmaps(client).list_metadata(&history_selector()).await?;
+    let mut revisions: Vec<RevisionInfo> = configmaps.items.iter().filter_map(|cm| RevisionInfo::of(&cm.metadata)).collect();
+    revisions.sort_by_key(|r| r.revision);
+    Ok(revisions)
+}
+
+// One revision of the history, with its content
+pub async fn find_revision(client: &Client, revision: u64) -> Result<Revision> {
+    let configmaps = history_configmaps(client);
+    let prefix = history_name();
+    let not_found = || Error::NotFound(format!("revision {revision}"));
+    let first = configmaps.get_opt(&revision_name(&prefix, revision, 0)).await?.ok_or_else(not_found)?;
+    let info = RevisionInfo::of(&first.metadata).ok_or_else(not_found)?;
+    let parts: usize = first.metadata.annotations.as_ref()
+        .and_then(|annotations| annotations.get(PARTS_ANNOTATION))
+        .and_then(|parts| parts.parse().ok())
+        .unwrap_or(1);
+    let mut hosts = hosts_of(first);
+    for part in 1..parts {
+        let name = revision_name(&prefix, revision, part);
+        let cm = configmaps.get_opt(&name).await?
+            .ok_or_else(|| Error::NotFound(format!("ConfigMap {name} of revision {revision}")))?;
+        hosts.push_str(&hosts_of(cm));
+    }
+    Ok(Revision { revision, at: info.at, summary: info.summary, hosts })
+}
+
+fn hosts_of(cm: ConfigMap) -> String {
+    cm.data.and_then(|mut data| data.remove(HOSTS_KEY)).unwrap_or_default()
+}
+
+// Write the ConfigMaps of a revision, the first one last so that the revision
+// only shows once complete
+async fn write_revision(configmaps: &Api<ConfigMap>, revision: &Revision) -> Result<()> {
+    let params = PatchParams::apply(&CONFIG.field_manager).force();
+    let parts = revision_configmaps(&history_name(), &CONFIG.host_configmap_name, revision, CONFIG.history_part_bytes as usize);
+    for cm in parts.iter().rev() {
+        let name = cm.metadata.name.as_deref().unwrap_or_default();
+        configmaps.patch(name, &params, &Patch::Apply(cm)).await?;
+    }
+    Ok(())
+}
+
+// Record a write in the history, each revision in its own ConfigMaps, and delete
+// the revisions beyond the limit. The content before the first recorded write
+// is kept as well, so that it can be restored.
+pub async fn record_revision(client: &Client, before: &HostRecords, after: &HostRecords, summary: String) -> Result<()> {
+    let configmaps = history_configmaps(client);
+    let existing = configmaps.list_metadata(&history_selector()).await?.items;
+    let mut revisions: Vec<u64> = existing.iter().filter_map(|cm| RevisionInfo::of(&cm.metadata)).map(|r| r.revision).collect();
+    let at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut next = revisions.iter().max().map_or(1, |latest| latest + 1);
+    if revisions.is_empty() {
+        let summary = "content before the first recorded write".into();
+        write_revision(&configmaps, &Revision { revision: next, at: at.clone(), summary, hosts: format_records(before) }).await?;
+        revisions.push(next);
+        next += 1;
+    }
+    write_revision(&configmaps, &Revision { revision: next, at, summary, hosts: format_records(after) }).await?;
+    revisions.push(next);
+
+    // older revisions and the parts of revisions never completed
+    let Some(oldest) = oldest_kept(revisions, CONFIG.history_limit) else {
+        return Ok(());
+    };
+    for cm in &existing {
+        let revision = cm.metadata.labels.as_ref()
+            .and_then(|labels| labels.get(REVISION_LABEL))
+            .and_then(|revision| revision.parse::<u64>().ok());
+        let Some(name) = cm.metadata.name.as_deref() else { continue };
+        if revision.is_some_and(|revision| revision < oldest) {
+            match configmaps.delete(name, &DeleteParams::default()).await {
+                Ok(_) => debug!("deleted revision ConfigMap {name}"),
+                Err(kube::Error::Api(e)) if e.code == 404 => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::Serialize;
+use tokio::process::Command;
+use tokio::sync::{watch, Notify};
+use tracing::{debug, info, warn};
+
+use crate::config::CONFIG;
+
+// Delay before the first retry of a failed hook, doubled on each retry
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+// Output of a command logged at most, in bytes
+const MAX_OUTPUT_LEN: usize = 4096;
+
+// Write passed to the hooks, as the JSON body of the callbacks and in the
+// environment of the commands
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HookPayload {
+    pub configmap: String,
+    pub namespace: String,
+    // Checksum of the written hosts content
+    pub checksum: String,
+    pub summary: String,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+// Environment of the hook commands
+pub fn hook_env(payload: &HookPayload) -> Vec<(&'static str, String)> {
+    vec![
+        ("HOSTS_CONFIGMAP", payload.configmap.clone()),
+        ("HOSTS_NAMESPACE", payload.namespace.clone()),
+        ("HOSTS_CHECKSUM", payload.checksum.clone()),
+        ("HOSTS_SUMMARY", payload.summary.clone()),
+        ("HOSTS_CREATED", payload.created.join(",")),
+        ("HOSTS_UPDATED", payload.updated.join(",")),
+        ("HOSTS_DELETED", payload.deleted.join(",")),
+        ("HOSTS_CHANGES_JSON", serde_json::to_string(payload).unwrap_or_default()),
+    ]
+}
+
+// Handle to the task running the hooks, one write after the other. The hooks
+// of a write still pending when a newer write is done are skipped: only the
+// latest payload waits, the hooks can't fall behind the writes.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pending: Arc<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    payload: Mutex<Option<HookPayload>>,
+    notify: Notify,
+    // True from a write until its hooks ran
+    busy: watch::Sender<bool>,
+}
+
+impl Hooks {
+    // Queue the hooks of a write, they run in the background
+    pub fn notify(&self, payload: HookPayload) {
+        let mut pending = self.pending.payload.lock().unwrap();
+        if let Some(skipped) = pending.replace(payload) {
+            debug!("post-write hooks of hosts checksum {} skipped for a newer write", skipped.checksum);
+        }
+        self.pending.busy.send_replace(true);
+        self.pending.notify.notify_one();
+    }
+
+    // Wait for the payload of the latest write
+    pub async fn next(&self) -> HookPayload {
+        loop {
+            if let Some(payload) = self.pending.payload.lock().unwrap().take() {
+                return payload;
+            }
+            self.pending.notify.notified().await;
+        }
+    }
+
+    // Mark the hooks of the latest payload as run, idle unless a newer write came
+    pub fn done(&self) {
+        let pending = self.pending.payload.lock().unwrap();
+        if pending.is_none() {
+            self.pending.busy.send_replace(false);
+        }
+    }
+
+    // Wait until the hooks of every write ran, before the process exits
+    pub async fn close(&self) {
+        // the sender lives in `self`, waiting can't fail
+        _ = self.pending.busy.subscribe().wait_for(|busy| !busy).await;
+    }
+}
+
+// Start the hooks task, None when no hook is configured
+pub fn spawn_hooks() -> Option<Hooks> {
+    if CONFIG.post_write_exec.is_empty() && CONFIG.post_write_url.is_empty() {
+        return None;
+    }
+    let timeout = Duration::from_secs(CONFIG.post_write_timeout_secs);
+    let http = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("post-write hooks disabled: {e}");
+            return None;
+        }
+    };
+    let hooks = Hooks::default();
+    tokio::spawn(run_hooks(hooks.clone(), http, timeout));
+    Some(hooks)
+}
+
+async fn run_hooks(hooks: Hooks, http: reqwest::Client, timeout: Duration) {
+    let retries = CONFIG.post_write_retries;
+    loop {
+        let payload = hooks.next().await;
+        for command in &CONFIG.post_write_exec {
+            _ = with_retries(&format!("post-write command {command:?}"), retries, RETRY_BACKOFF,
+                || exec(command, &payload, timeout)).await;
+        }
+        for url in &CONFIG.post_write_url {
+            _ = with_retries(&format!("post-write callback {url}"), retries, RETRY_BACKOFF,
+                || post(&http, url, &payload)).await;
+        }
+        hooks.done();
+    }
+}
+
+// Run a hook until it succeeds, `retries` more times at most with the backoff
+// doubled on each retry. Return the last error when every attempt failed.
+pub async fn with_retries<F, Fut>(hook: &str, retries: u32, mut backoff: Duration, mut run: F) -> Result<(), String>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<(), String>>,
+{
+    let mut attempt = 0;
+    loop {
+        match run().await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt == retries => {
+                warn!("{hook} failed (attempt {}/{}): {e}", attempt + 1, retries + 1);
+                return Err(e);
+            }
+            Err(e) => warn!("{hook} failed (attempt {}/{}): {e}", attempt + 1, retries + 1),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+// Run a command with `sh -c`, killed on timeout
+pub async fn exec(command: &str, payload: &HookPayload, timeout: Duration) -> Result<(), String> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(hook_env(payload))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(timeout, child).await
+        .map_err(|_| format!("timed out after {}s", timeout.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    let (stdout, stderr) = (excerpt(&output.stdout), excerpt(&output.stderr));
+    if output.status.success() {
+        info!("post-write command {command:?} done, stdout: {stdout:?}, stderr: {stderr:?}");
+        Ok(())
+    } else {
+        Err(format!("{}, stdout: {stdout:?}, stderr: {stderr:?}", output.status))
+    }
+}
+
+pub async fn post(http: &reqwest::Client, url: &str, payload: &HookPayload) -> Result<(), String> {
+    let response = http.post(url).json(payload).send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if status.is_success() {
+        info!("post-write callback {url} done: {status}");
+        Ok(())
+    } else {
+        Err(format!("{status}: {}", excerpt(body.as_bytes())))
+    }
+}
+
+fn excerpt(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(&output[..output.len().min(MAX_OUTPUT_LEN)]);
+    output.trim().to_string()
+}
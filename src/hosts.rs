@@ -1,139 +1,443 @@
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use once_cell::sync::Lazy;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
 use regex::Regex;
-use tracing::info;
+use std::time::Duration;
+use tracing::{debug, info, instrument, warn};
+use crate::cache::CACHE;
 use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use crate::metrics::{observe_records, observe_skipped_lines, observe_write};
+use crate::records::RecordType;
+use crate::shards::{fnv1a, plan_shards, shard_names, ShardManifest, MANIFEST_KEY, SHARD_OF_LABEL};
 
-use kube::{api::{Api, ListParams, Patch, PatchParams, PostParams}, Client};
+use kube::{api::{Api, DeleteParams, Patch, PatchParams}, Client};
 use k8s_openapi::api::core::v1::ConfigMap;
-use serde_json::json;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-static HOST_REGEXP: &str = r"(?m)^\s*(?P<address>[0-9\.:]+)\s+(?P<name>[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?(\.[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*)\s*$";
+static HOST_REGEXP: &str = r"(?m)^\s*(?P<address>[0-9A-Fa-f\.:]+)\s+(?P<name>[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?(\.[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*)\s*$";
+static NAME_REGEXP: &str = r"^[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?(\.[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*$";
 
-async fn get_configmaps() -> Result<Api<ConfigMap>, kube::Error> {
-    // Création du client
-    let client: Client = Client::try_default().await?;
+// Annotation of the hosts ConfigMap listing the operator-owned names, as a JSON array
+pub const OWNED_ANNOTATION: &str = "host-webhook-provider/operator-owned";
+// Annotations of the hosts ConfigMap with the checksum of the content and the time of the last write
+pub const CHECKSUM_ANNOTATION: &str = "host-webhook-provider/checksum";
+pub const LAST_UPDATED_ANNOTATION: &str = "host-webhook-provider/last-updated";
+
+const MAX_WRITE_BACKOFF: Duration = Duration::from_secs(5);
+// Reads of sharded records restarted at most when a write replaces the shards meanwhile
+const SHARD_READ_ATTEMPTS: usize = 3;
+
+// Addresses of each name, as stored in a hosts file
+pub type HostRecords = HashMap<String,HashSet<String>>;
+
+// Record types a hosts file can store
+pub const SUPPORTED_RECORD_TYPES: &[RecordType] = &[RecordType::A, RecordType::AAAA];
+
+// Check that a name can be written to and read back from a hosts file
+pub fn is_valid_host_name(name: &str) -> bool {
+    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(NAME_REGEXP).unwrap());
+    RE.is_match(name)
+}
+
+// Namespace of the hosts ConfigMap, defaults to the namespace of the client
+pub fn host_namespace(client: &Client) -> &str {
+    CONFIG.host_configmap_namespace.as_deref().unwrap_or_else(|| client.default_namespace())
+}
+
+pub fn get_configmaps(client: &Client) -> Api<ConfigMap> {
     // Création d'une interface pour interroger les ConfigMap
-    let configmaps: Api<ConfigMap> = Api::namespaced(Client::try_default().await?,
-         CONFIG.host_configmap_namespace.as_deref().unwrap_or_else(|| client.default_namespace()));
-    Ok(configmaps)
+    Api::namespaced(client.clone(), host_namespace(client))
 }
 
-// return HashMap<name, ips>
-pub async fn read_host() -> Result<HashMap<String,HashSet<String>>, kube::Error> {
-    let mut records: HashMap<String,HashSet<String>> = HashMap::new();
-    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(&HOST_REGEXP).unwrap());
+// Records read from the ConfigMap, with the version they were read at
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostsSnapshot {
+    pub records: HostRecords,
+    // Names pinned by operators through the admin API, external-dns can't change them
+    pub owned: BTreeSet<String>,
+    // None when the ConfigMap doesn't exist yet
+    pub resource_version: Option<String>,
+    // ConfigMaps holding the records when they are sharded, from the manifest
+    pub shards: Vec<String>,
+    // Checksum of the stored hosts content, None when the ConfigMap doesn't exist yet
+    pub checksum: Option<String>,
+}
 
-    let configmaps = get_configmaps().await?;
+static HOST_RE: Lazy<Regex> = Lazy::new(|| Regex::new(HOST_REGEXP).unwrap());
 
-    // Récupération de la config map conténant les données
-    let cm: ConfigMap = configmaps.get(&CONFIG.host_configmap_name).await?;
-    
-    // Récupération du contenu du fichier host dans la clé du configmap
-    let lines: String = match cm.data {
-        Some(data) => {
-            match data.get(&CONFIG.host_configmap_key) {
-                Some(v) => v.to_string(),
-                None => String::from(""),
-            }
-        },
-        None => String::from(""),
-    };
+// Parse the content of a hosts file, return HashMap<name, ips>
+pub fn parse_hosts(lines: &str) -> HostRecords {
+    let mut records: HostRecords = HashMap::new();
 
     // Parcourt chaque ligne du fichier hosts
     for line in lines.lines() {
-        if let Some(parts) = RE.captures(&line) {
+        if let Some(parts) = HOST_RE.captures(line) {
             // Extraction et conversion des captures en String
             let name = parts.name("name").unwrap().as_str().to_string();
             let address = parts.name("address").unwrap().as_str().to_string();
 
             // Ajout de l'adresse dans le vecteur correspondant à la clé 'name'
             records.entry(name)
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(address);
         } else {
             info!("Skip host line: {line}");
         }
     }
 
-    // Renvoi le résultat
-    Ok(records)
+    records
 }
 
-fn format_records(records: &HashMap<String,HashSet<String>>) -> String {
-    records.iter().fold(String::new(), |mut acc, (name, ips)| {
-        for ip in ips {
-            acc.push_str(&format!("{ip} {name}\n"));
+// Number of lines of a hosts file that `parse_hosts` skips
+pub fn skipped_lines(lines: &str) -> usize {
+    lines.lines().filter(|line| !HOST_RE.is_match(line)).count()
+}
+
+// Lines of a hosts file that `parse_hosts` would skip or store with an invalid
+// address, blank lines and comments aside
+pub fn validate_hosts(lines: &str) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+    for (i, line) in lines.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
         }
-        acc
-    })
+        match HOST_RE.captures(line) {
+            Some(parts) => {
+                let address = parts.name("address").unwrap().as_str();
+                if address.parse::<IpAddr>().is_err() {
+                    errors.push(format!("line {}: {address:?} isn't an IP address", i + 1));
+                }
+            }
+            None => errors.push(format!("line {}: expected \"<address> <name>\": {trimmed:?}", i + 1)),
+        }
+    }
+    errors
 }
 
-async fn exists_cm(configmaps: &Api<ConfigMap>)-> bool {
-    // Paramètres de la liste (ici on récupère toutes les ConfigMaps)
-    static LP: Lazy<ListParams> = Lazy::new(|| ListParams::default()
-            .fields(&format!("metadata.name={}", CONFIG.host_configmap_name.clone())));
+pub(crate) fn hosts_data(cm: &ConfigMap) -> &str {
+    // Récupération du contenu du fichier host dans la clé du configmap
+    cm.data.as_ref()
+        .and_then(|data| data.get(&CONFIG.host_configmap_key))
+        .map(String::as_str)
+        .unwrap_or_default()
+}
 
-    // Lister toutes les ConfigMaps dans le namespace
-    let list = match configmaps.list(&LP).await {
-        Ok(v) => v,
-        Err(_) => {return false;}
-    };
+// Records stored in a hosts ConfigMap. When they are sharded, the records are
+// left empty and `shards` lists the ConfigMaps to read them from.
+pub fn snapshot_of(cm: &ConfigMap) -> HostsSnapshot {
+    let lines = hosts_data(cm);
+    let manifest = manifest_of(cm);
+
+    let owned = cm.metadata.annotations.as_ref()
+        .and_then(|annotations| annotations.get(OWNED_ANNOTATION))
+        .map(|names| serde_json::from_str(names).unwrap_or_else(|e| {
+            warn!("ignoring invalid {OWNED_ANNOTATION} annotation: {e}");
+            BTreeSet::new()
+        }))
+        .unwrap_or_default();
+
+    HostsSnapshot {
+        records: parse_hosts(lines),
+        owned,
+        resource_version: cm.metadata.resource_version.clone(),
+        shards: manifest.as_ref().map(|m| m.configmaps.clone()).unwrap_or_default(),
+        // the generation of sharded records is the checksum of the whole content
+        checksum: Some(manifest.map_or_else(|| hosts_checksum(lines), |m| m.generation)),
+    }
+}
+
+fn manifest_of(cm: &ConfigMap) -> Option<ShardManifest> {
+    cm.data.as_ref()
+        .and_then(|data| data.get(MANIFEST_KEY))
+        .and_then(|manifest| serde_json::from_str(manifest).map_err(|e| {
+            warn!("ignoring invalid {MANIFEST_KEY} manifest: {e}");
+        }).ok())
+}
+
+// Shard ConfigMaps listed in the manifest of the hosts ConfigMap, if any
+fn shards_of(cm: &ConfigMap) -> Vec<String> {
+    manifest_of(cm).map(|manifest| manifest.configmaps).unwrap_or_default()
+}
+
+// Read the records from the API server, with their shards
+#[instrument(skip_all, fields(configmap = %CONFIG.host_configmap_name))]
+pub async fn read_host(client: &Client) -> Result<HostsSnapshot> {
+    let configmaps = get_configmaps(client);
+    for _ in 0..SHARD_READ_ATTEMPTS {
+        // Récupération de la config map conténant les données
+        let Some(cm) = configmaps.get_opt(&CONFIG.host_configmap_name).await? else {
+            return Ok(HostsSnapshot::default());
+        };
+        let mut snapshot = snapshot_of(&cm);
+        if snapshot.shards.is_empty() {
+            observe_records(&snapshot.records);
+            observe_skipped_lines(skipped_lines(hosts_data(&cm)));
+            return Ok(snapshot);
+        }
+        match read_shards(&configmaps, &snapshot.shards).await? {
+            Some((records, skipped)) => {
+                snapshot.records = records;
+                observe_records(&snapshot.records);
+                observe_skipped_lines(skipped);
+                if CACHE.is_synced() {
+                    CACHE.store_shards(&snapshot);
+                }
+                return Ok(snapshot);
+            }
+            None => debug!("shards of ConfigMap {} replaced while reading them", CONFIG.host_configmap_name),
+        }
+    }
+    Err(Error::Unavailable(format!("shards of ConfigMap {} replaced on every read", CONFIG.host_configmap_name)))
+}
+
+// Records of the shards and their skipped lines, None when one is gone because
+// a newer write replaced them
+#[instrument(skip_all, fields(shards = names.len()))]
+async fn read_shards(configmaps: &Api<ConfigMap>, names: &[String]) -> Result<Option<(HostRecords, usize)>> {
+    let (mut records, mut skipped) = (HostRecords::new(), 0);
+    for name in names {
+        let Some(cm) = configmaps.get_opt(name).await? else {
+            return Ok(None);
+        };
+        records.extend(parse_hosts(hosts_data(&cm)));
+        skipped += skipped_lines(hosts_data(&cm));
+    }
+    Ok(Some((records, skipped)))
+}
+
+// Read the records from the watch cache once synced, from the API server otherwise.
+// The cache only watches the hosts ConfigMap: sharded records are read from the API
+// server once per generation of the manifest, then served from the cache.
+pub async fn current_host(client: &Client) -> Result<HostsSnapshot> {
+    match CACHE.snapshot() {
+        Some(snapshot) => Ok(snapshot),
+        None => read_host(client).await,
+    }
+}
 
-    // Parcourir la liste et vérifier si la ConfigMap existe
-    return list.iter().any(|cm| cm.metadata.name.as_deref() == Some(&CONFIG.host_configmap_name));
+// Order of the names in the rendered hosts
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HostsOrder {
+    // Sorted by name
+    #[default]
+    Name,
+    // Sorted by domain, from the top-level label, so that the names of a domain are grouped
+    Domain,
+}
 
+// Render records as hosts file lines, sorted by name
+pub fn format_records(records: &HostRecords) -> String {
+    format_records_by(records, HostsOrder::Name)
 }
 
-async fn create_cm(configmaps: &Api<ConfigMap>, records: &HashMap<String,HashSet<String>>)-> Result<(),kube::Error> {
-    // Créer les données pour la ConfigMap
-    let mut data = BTreeMap::new();
-    data.insert(CONFIG.host_configmap_name.clone(), format_records(&records));
+// Render records canonically: the same records always give the same content.
+// Addresses are sorted numerically, IPv4 before IPv6, invalid ones last.
+pub fn format_records_by(records: &HostRecords, order: HostsOrder) -> String {
+    let mut names: Vec<&String> = records.keys().collect();
+    match order {
+        HostsOrder::Name => names.sort(),
+        HostsOrder::Domain => names.sort_by_cached_key(|name| name.rsplit('.').collect::<Vec<_>>()),
+    }
+    let mut content = String::new();
+    for name in names {
+        let mut ips: Vec<&String> = records[name].iter().collect();
+        ips.sort_by_cached_key(|ip| (ip.parse::<IpAddr>().map_err(|_| ()), ip.as_str()));
+        for ip in ips {
+            content.push_str(&format!("{ip} {name}\n"));
+        }
+    }
+    content
+}
+
+// Checksum of rendered hosts, stable across releases
+pub fn hosts_checksum(content: &str) -> String {
+    format!("{:016x}", fnv1a(content.as_bytes()))
+}
+
+// ConfigMap fields owned by the provider: `data`, either the configured key or
+// the shard manifest, the checksum and last-updated annotations, and the
+// operator-owned annotation, left out when no name is owned
+fn host_cm(snapshot: &HostsSnapshot, data: BTreeMap<String, String>, checksum: &str) -> ConfigMap {
+    let mut annotations = BTreeMap::from([
+        (CHECKSUM_ANNOTATION.to_string(), checksum.to_string()),
+        (LAST_UPDATED_ANNOTATION.to_string(), Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+    ]);
+    if !snapshot.owned.is_empty() {
+        annotations.insert(OWNED_ANNOTATION.to_string(), serde_json::to_string(&snapshot.owned).unwrap_or_default());
+    }
 
-    // Définir la ConfigMap
-    let cm = ConfigMap {
+    ConfigMap {
         metadata: kube::api::ObjectMeta {
             name: Some(CONFIG.host_configmap_name.clone()),
+            resource_version: snapshot.resource_version.clone(),
+            annotations: Some(annotations),
             ..Default::default()
         },
         data: Some(data),
         ..Default::default()
-    };
-
-    // Créer la ConfigMap sur le cluster
-    let pp = PostParams::default();
-    configmaps.create(&pp, &cm).await?;
+    }
+}
 
-    Ok(())
+fn shard_cm(name: &str, records: &HostRecords) -> ConfigMap {
+    ConfigMap {
+        metadata: kube::api::ObjectMeta {
+            name: Some(name.to_string()),
+            labels: Some(BTreeMap::from([(SHARD_OF_LABEL.to_string(), CONFIG.host_configmap_name.clone())])),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(CONFIG.host_configmap_key.clone(), format_records_by(records, CONFIG.hosts_order))])),
+        ..Default::default()
+    }
 }
 
-async fn patch_cm(configmaps: &Api<ConfigMap>, records: &HashMap<String,HashSet<String>>)-> Result<(),kube::Error> {
-    // Création du dictionnaire des données à modifier
-    let mut new_data: BTreeMap<&String, String> = BTreeMap::new();
-    new_data.insert(&CONFIG.host_configmap_key, format_records(&records)); // Exemple de modification
+// Write records with server-side apply, creating the ConfigMap when missing.
+//
+// The `data.<key>` field and the operator-owned annotation are owned by the configured
+// field manager, other fields of the ConfigMap are left to their managers. When the
+// snapshot has a resource version, the apply only succeeds if the ConfigMap is still
+// at that version (409 otherwise).
+//
+// Another applier owning the same key (e.g. Argo CD syncing a manifest that sets
+// `data.<key>`, with server-side apply or not) conflicts with the provider:
+// - with --force-conflicts (default), the provider takes the ownership of the key.
+//   The other applier then reports a drift and overwrites the records on its next sync,
+//   unless it ignores the key (Argo CD: `ignoreDifferences` with `managedFieldsManagers`
+//   set to the provider field manager, or no `data.<key>` in the manifest).
+// - without it, the apply is rejected with a 409 naming the other manager and the
+//   records aren't written until the key is released.
+//
+// Records rendered larger than --shard-threshold-bytes are split by name hash across
+// shard ConfigMaps named after a hash of the content. The shards are written first,
+// then the hosts ConfigMap gets the manifest listing them in place of the hosts key,
+// and the shards of the previous content are deleted.
+#[instrument(skip_all, fields(configmap = %CONFIG.host_configmap_name, names = snapshot.records.len()))]
+pub async fn write_host(client: &Client, snapshot: &HostsSnapshot) -> Result<ConfigMap, kube::Error> {
+    // Création d'une interface pour interroger les ConfigMap
+    let configmaps = get_configmaps(client);
 
-    // Créer un patch JSON pour modifier le ConfigMap
-    let patch = json!({
-        "data": new_data
-    });
+    let mut params = PatchParams::apply(&CONFIG.field_manager);
+    if CONFIG.force_conflicts {
+        params = params.force();
+    }
+    let content = format_records_by(&snapshot.records, CONFIG.hosts_order);
+    let checksum = hosts_checksum(&content);
+    let Some(parts) = plan_shards(&snapshot.records, CONFIG.shard_threshold_bytes) else {
+        let data = BTreeMap::from([(CONFIG.host_configmap_key.clone(), content)]);
+        let cm = host_cm(snapshot, data, &checksum);
+        let written = configmaps.patch(&CONFIG.host_configmap_name, &params, &Patch::Apply(&cm)).await;
+        if written.is_ok() {
+            delete_shards(&configmaps, &snapshot.shards).await;
+        }
+        return written;
+    };
 
-    // Paramètres de patch : On spécifie que c'est un merge patch
-    static PATCH_PARAMS: Lazy<PatchParams> = Lazy::new(|| PatchParams::apply("external-dns-webhhok"));
+    let generation = checksum;
+    let shards = shard_names(&CONFIG.host_configmap_name, &generation, parts.len());
+    info!("hosts content is {} bytes, writing it in {} shards", content.len(), shards.len());
+    let written = async {
+        for (name, records) in shards.iter().zip(&parts) {
+            configmaps.patch(name, &params, &Patch::Apply(&shard_cm(name, records))).await?;
+        }
+        let manifest = ShardManifest { by: "hash".into(), generation, configmaps: shards.clone() };
+        let data = BTreeMap::from([(MANIFEST_KEY.to_string(), serde_json::to_string(&manifest).unwrap_or_default())]);
+        let cm = host_cm(snapshot, data, &manifest.generation);
+        configmaps.patch(&CONFIG.host_configmap_name, &params, &Patch::Apply(&cm)).await
+    }.await;
 
-    // Patcher le ConfigMap
-    configmaps.patch(&CONFIG.host_configmap_name, &PATCH_PARAMS, &Patch::Merge(&patch)).await?;
+    // the previous shards after a write, the new ones after a failure unless a
+    // concurrent write of the same content uses them
+    let stale: Vec<String> = match &written {
+        Ok(_) => snapshot.shards.iter().filter(|name| !shards.contains(name)).cloned().collect(),
+        Err(_) => {
+            let current = configmaps.get_opt(&CONFIG.host_configmap_name).await.ok().flatten()
+                .map(|cm| shards_of(&cm))
+                .unwrap_or_default();
+            shards.into_iter().filter(|name| !current.contains(name) && !snapshot.shards.contains(name)).collect()
+        }
+    };
+    delete_shards(&configmaps, &stale).await;
+    written
+}
 
-    Ok(())
+// Failures only leave unused ConfigMaps behind, labelled with the hosts ConfigMap name
+#[instrument(skip_all, fields(shards = names.len()))]
+async fn delete_shards(configmaps: &Api<ConfigMap>, names: &[String]) {
+    for name in names {
+        match configmaps.delete(name, &DeleteParams::default()).await {
+            Ok(_) => debug!("deleted shard ConfigMap {name}"),
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => warn!("failed to delete shard ConfigMap {name}: {e}"),
+        }
+    }
 }
 
-pub async fn write_host(records: &HashMap<String,HashSet<String>>) -> Result<(),kube::Error> {
-    // Création d'une interface pour interroger les ConfigMap
-    let configmaps = get_configmaps().await?;
+// A 409 Conflict on the apply is either a failed resourceVersion precondition or a
+// conflict with another field manager, that retrying won't solve. It's the latter when
+// no precondition was sent, or when the ConfigMap still has the version sent.
+pub fn is_field_conflict(sent_version: Option<&str>, current_version: Option<&str>) -> bool {
+    sent_version.is_none() || sent_version == current_version
+}
 
-    if exists_cm(&configmaps).await {
-        patch_cm(&configmaps, &records).await
-    } else {
-        create_cm(&configmaps, &records).await
+// Read, modify and write the records, retrying with a bounded backoff
+// when the ConfigMap was modified between the read and the write.
+// The first attempt reads from the watch cache, retries from the API server.
+#[instrument(skip_all, fields(configmap = %CONFIG.host_configmap_name))]
+pub async fn update_host<F, R>(client: &Client, mut apply: F) -> Result<R>
+where
+    F: FnMut(&HostsSnapshot) -> Result<(HostsSnapshot, R)>,
+{
+    let mut backoff = Duration::from_millis(CONFIG.write_retry_backoff_ms);
+    // version sent with the last write rejected by a conflict, and the reason given
+    let mut conflict: Option<(Option<String>, String)> = None;
+    for attempt in 0..=CONFIG.write_retries {
+        let snapshot = if attempt == 0 { current_host(client).await? } else { read_host(client).await? };
+        if let Some((sent, message)) = conflict.take() {
+            if is_field_conflict(sent.as_deref(), snapshot.resource_version.as_deref()) {
+                return Err(Error::Conflict(format!("ConfigMap {}: {message}", CONFIG.host_configmap_name)));
+            }
+        }
+        let (mut updated, result) = apply(&snapshot)?;
+        if updated.owned == snapshot.owned
+            && snapshot.checksum == Some(hosts_checksum(&format_records_by(&updated.records, CONFIG.hosts_order))) {
+            debug!("hosts content unchanged, nothing to write");
+            return Ok(result);
+        }
+        updated.resource_version = snapshot.resource_version;
+        match write_host(client, &updated).await {
+            Ok(cm) => {
+                observe_write(true);
+                observe_records(&updated.records);
+                if CACHE.is_synced() {
+                    let written = snapshot_of(&cm);
+                    CACHE.store_written(HostsSnapshot { records: updated.records, ..written });
+                }
+                return Ok(result);
+            }
+            Err(kube::Error::Api(e)) if e.code == 409 && e.reason == "Conflict" => {
+                if updated.resource_version.is_none() {
+                    return Err(Error::Conflict(format!("ConfigMap {}: {}", CONFIG.host_configmap_name, e.message)));
+                }
+                warn!("ConfigMap {} conflict (attempt {}/{}): {}",
+                    CONFIG.host_configmap_name, attempt + 1, CONFIG.write_retries + 1, e.message);
+                conflict = Some((updated.resource_version.clone(), e.message));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if attempt < CONFIG.write_retries {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_WRITE_BACKOFF);
+        }
+    }
+    // the last conflict is told apart by reading the version once more
+    if let Some((sent, message)) = conflict {
+        if is_field_conflict(sent.as_deref(), read_host(client).await?.resource_version.as_deref()) {
+            return Err(Error::Conflict(format!("ConfigMap {}: {message}", CONFIG.host_configmap_name)));
+        }
     }
+    Err(Error::Conflict(format!("ConfigMap {} still modified concurrently after {} attempts",
+        CONFIG.host_configmap_name, CONFIG.write_retries + 1)))
 }
@@ -1,4 +1,22 @@
+pub mod adjust;
+pub mod admin;
+pub mod cache;
+pub mod changes;
+pub mod cli;
+pub mod client;
 pub mod config;
+pub mod dryrun;
+pub mod error;
+pub mod events;
 pub mod records;
+pub mod reload;
+pub mod history;
+pub mod hooks;
 pub mod hosts;
-pub mod health;
\ No newline at end of file
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod negotiation;
+pub mod shards;
+pub mod telemetry;
+pub mod writer;
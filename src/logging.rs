@@ -0,0 +1,74 @@
+use clap::ValueEnum;
+use opentelemetry_sdk::trace::Tracer;
+use salvo::Depot;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::error::{Error, Result};
+
+// Format of the log lines
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    // Human readable lines
+    #[default]
+    Text,
+    // One JSON object per line, with the fields and spans of the event
+    Json,
+}
+
+// Parse `RUST_LOG` style directives, e.g. `info,host_webhook_provider=debug,kube=warn`.
+// Without directives the level is debug with --debug, info otherwise.
+pub fn log_filter(directives: Option<&str>, debug: bool) -> Result<EnvFilter> {
+    let directives = match directives.map(str::trim) {
+        Some(directives) if !directives.is_empty() => directives,
+        _ if debug => "debug",
+        _ => "info",
+    };
+    EnvFilter::builder().parse(directives)
+        .map_err(|e| Error::validation(format!("invalid log filter {directives:?}"), vec![e.to_string()]))
+}
+
+// Handle changing the log filter of the running process
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    // Current directives
+    pub fn filter(&self) -> String {
+        self.handle.with_current(|filter| filter.to_string()).unwrap_or_default()
+    }
+
+    // Replace the directives, the previous ones stay on error
+    pub fn set_filter(&self, directives: &str) -> Result<String> {
+        let filter = log_filter(Some(directives), false)?;
+        self.handle.reload(filter)
+            .map_err(|e| Error::Unavailable(format!("log filter can't be changed: {e}")))?;
+        Ok(self.filter())
+    }
+}
+
+// Install the global subscriber, spans are exported with the tracer when given
+pub fn init_logging(format: LogFormat, filter: EnvFilter, writer: BoxMakeWriter, tracer: Option<Tracer>) -> LogLevel {
+    let (filter, handle) = reload::Layer::new(filter);
+    let (text, json) = match format {
+        LogFormat::Text => (Some(fmt::layer().with_writer(writer)), None),
+        LogFormat::Json => (None, Some(fmt::layer().json().with_writer(writer))),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+    LogLevel { handle }
+}
+
+// Log level handle injected in the depot at startup
+pub fn obtain_log_level(depot: &Depot) -> LogLevel {
+    depot.obtain::<LogLevel>()
+        .expect("log level handle isn't injected in the depot")
+        .clone()
+}
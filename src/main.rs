@@ -1,62 +1,109 @@
-use host_webhook_provider::config::CONFIG;
-use host_webhook_provider::health::get_healthz;
-use host_webhook_provider::records::{get_records, post_adjustendpoints, post_records};
+use host_webhook_provider::cli::{self, Command};
+use host_webhook_provider::client::build_client;
+use host_webhook_provider::config::{DomainFilter, CONFIG};
+use host_webhook_provider::error::ErrorBody;
+use host_webhook_provider::admin;
+use host_webhook_provider::cache::run_watcher;
+use host_webhook_provider::history::history_name;
+use host_webhook_provider::health::{get_healthz, get_livez, get_readyz, run_heartbeat};
+use host_webhook_provider::logging::{init_logging, log_filter, LogLevel};
+use host_webhook_provider::metrics::{get_metrics, track_requests};
+use host_webhook_provider::negotiation::{negotiate, webhook_openapi};
+use host_webhook_provider::telemetry::{trace_requests, tracer_provider};
+use host_webhook_provider::records::{get_records, post_adjustendpoints, post_plan, post_records};
+use host_webhook_provider::writer::spawn_writer;
 use salvo::logging::Logger;
+use salvo::oapi::naming::{set_namer, FlexNamer};
+use salvo::oapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use salvo::conn::tcp::TcpAcceptor;
 use salvo::server::ServerHandle;
 use salvo::prelude::*;
 use tokio::{signal, task};
 use futures::future::join_all;
 use std::time::Duration;
-use tracing::{debug, error, info};
+use opentelemetry::trace::TracerProvider;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
-#[handler]
-async fn get_root(req: &mut Request, res: &mut Response) {
-    let domain_filter = CONFIG.domain_filter.clone();
-    debug!("domain_filter: {:?}", &domain_filter);
+/// Negotiate the domain filter with external-dns
+#[endpoint(
+    tags("webhook"),
+    responses((status_code = 406, description = "Not Acceptable", body = ErrorBody)),
+)]
+async fn get_root() -> Json<DomainFilter> {
+    debug!("domain_filter: {:?}", &CONFIG.domain_filter);
+    Json(CONFIG.domain_filter.clone())
+}
 
-    match serde_json::to_string(&domain_filter) {
-        Ok(v) => {
-            res.status_code(StatusCode::OK);
-            res.render(Text::Json(v));
-        }
+#[tokio::main]
+async fn main() {
+    let filter = match log_filter(CONFIG.log_filter.as_deref(), CONFIG.debug) {
+        Ok(filter) => filter,
         Err(e) => {
-            error!("Erreur lors de la conversion en JSON: {}", e);
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Text::Plain("Erreur lors de la conversion en JSON"));
+            eprintln!("{e}");
+            for detail in e.details() {
+                eprintln!("  {detail}");
+            }
+            std::process::exit(1);
         }
-    }
-
-    // Set Content-Type Header with Accept Header
-    if let Some(v) = req.header("Accept") {
-        let accept_header_value: String = v;
-        if let Err(err) = res.add_header("Content-Type", accept_header_value, true) {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Text::Plain(format!("Failed to add header Content-Type: {}",err.to_string())));
-            return;
-        };
     };
-}
-
-#[handler]
-async fn alter_content_type(req: &mut Request) {
-    if let Some(v) = req.header("Content-Type") {
-        let content_type: String = v;
-        if content_type == "application/external.dns.webhook+json;version=1" {
-            if let Err(e) = req.add_header("Content-Type", "application/json;version=1", true) {
-                info!("Failed to replace Content-Type: {:?}", e);
-            } else if CONFIG.debug {
-                debug!("modified content-type header application/external.dns.webhook+json;version=1 -> application/json;version=1")
+    // logs on stderr for the commands, stdout is the exported content
+    let init_stderr = |filter| init_logging(CONFIG.log_format, filter, BoxMakeWriter::new(std::io::stderr), None);
+    let result = match &CONFIG.command {
+        None | Some(Command::Serve) => {
+            // spans exported over OTLP when the OTEL variables configure it
+            let telemetry = tracer_provider();
+            let provider = telemetry.as_ref().ok().and_then(|p| p.clone());
+            let tracer = provider.as_ref().map(|p| p.tracer(env!("CARGO_PKG_NAME")));
+            let log_level = init_logging(CONFIG.log_format, filter, BoxMakeWriter::new(std::io::stdout), tracer);
+            match &telemetry {
+                Ok(Some(_)) => info!("exporting traces over OTLP"),
+                Ok(None) => {}
+                Err(e) => warn!("traces not exported: {e}"),
             }
+            serve(log_level).await;
+            // flush the pending spans, the batch exporter blocks
+            if let Some(provider) = provider {
+                _ = task::spawn_blocking(move || provider.shutdown()).await;
+            }
+            Ok(())
+        }
+        Some(Command::Export(args)) => {
+            init_stderr(filter);
+            cli::export(args).await
         }
+        Some(Command::Import(args)) => {
+            init_stderr(filter);
+            cli::import(args).await
+        }
+        // offline commands, no cluster access
+        Some(Command::Validate(args)) => {
+            init_stderr(filter);
+            cli::validate(args)
+        }
+        Some(Command::Diff(args)) => {
+            init_stderr(filter);
+            match cli::diff(args) {
+                // like diff(1), the status tells whether the records differ
+                Ok(true) if args.exit_code => std::process::exit(1),
+                result => result.map(|_| ()),
+            }
+        }
+        Some(Command::Apply(args)) => {
+            init_stderr(filter);
+            cli::apply(args)
+        }
+    };
+    if let Err(e) = result {
+        error!("{e}");
+        for detail in e.details() {
+            error!("  {detail}");
+        }
+        std::process::exit(1);
     }
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(if CONFIG.debug {tracing::Level::DEBUG} else { tracing::Level::INFO} )
-        .init();
-
+async fn serve(log_level: LogLevel) {
     info!("Config: filters={}", &CONFIG.domain_filter.filters.join(","));
     info!("Config: exclude={}", &CONFIG.domain_filter.exclude.join(","));
     info!("Config: regex={}", &CONFIG.domain_filter.regex);
@@ -64,42 +111,132 @@ async fn main() {
     info!("Config: host_configmap_name={}", &CONFIG.host_configmap_name);
     info!("Config: host_configmap_namespace={}", CONFIG.host_configmap_namespace.as_deref().unwrap_or(""));
     info!("Config: host_configmap_key={}", &CONFIG.host_configmap_key);
+    info!("Config: kubeconfig={}", CONFIG.kubeconfig.as_ref().map(|p| p.display().to_string()).unwrap_or_default());
+    info!("Config: kube_context={}", CONFIG.kube_context.as_deref().unwrap_or(""));
+    info!("Config: kube_connect_timeout={}", &CONFIG.kube_connect_timeout);
+    info!("Config: kube_read_timeout={}", &CONFIG.kube_read_timeout);
+    info!("Config: kube_user_agent={}", &CONFIG.kube_user_agent);
+    info!("Config: watch_cache={}", &CONFIG.watch_cache);
+    info!("Config: field_manager={}", &CONFIG.field_manager);
+    info!("Config: force_conflicts={}", &CONFIG.force_conflicts);
+    info!("Config: write_retries={}", &CONFIG.write_retries);
+    info!("Config: write_retry_backoff_ms={}", &CONFIG.write_retry_backoff_ms);
+    info!("Config: write_coalesce_ms={}", &CONFIG.write_coalesce_ms);
+    info!("Config: restart_workloads={}", CONFIG.restart_workloads.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(","));
+    info!("Config: post_write_exec={:?}", &CONFIG.post_write_exec);
+    info!("Config: post_write_url={:?}", &CONFIG.post_write_url);
+    info!("Config: post_write_timeout_secs={}", &CONFIG.post_write_timeout_secs);
+    info!("Config: post_write_retries={}", &CONFIG.post_write_retries);
+    info!("Config: hosts_order={:?}", &CONFIG.hosts_order);
+    info!("Config: shard_threshold_bytes={}", &CONFIG.shard_threshold_bytes);
+    info!("Config: history_limit={}", &CONFIG.history_limit);
+    info!("Config: history_configmap_name={}", history_name());
+    info!("Config: history_part_bytes={}", &CONFIG.history_part_bytes);
+    info!("Config: events={}", &CONFIG.events);
+    info!("Config: event_interval_secs={}", &CONFIG.event_interval_secs);
     info!("Config: listen_addr={}", &CONFIG.listen_addr);
     info!("Config: health_listen_addr={}", &CONFIG.health_listen_addr);
+    info!("Config: openapi_listen_addr={}", CONFIG.openapi_listen_addr.as_deref().unwrap_or(&CONFIG.health_listen_addr));
+    info!("Config: admin_listen_addr={}", CONFIG.admin_listen_addr.as_deref().unwrap_or("unset, admin API not served"));
+    info!("Config: admin_token={}", if CONFIG.admin_token.is_some() { "set" } else { "unset, admin API disabled" });
+    info!("Config: on_create_existing={:?}", &CONFIG.change_policy.on_create_existing);
+    info!("Config: on_delete_missing={:?}", &CONFIG.change_policy.on_delete_missing);
+    info!("Config: on_update_mismatch={:?}", &CONFIG.change_policy.on_update_mismatch);
     info!("Config: dry_run={}", &CONFIG.dry_run);
     info!("Config: debug={}", &CONFIG.debug);
+    info!("Config: log_format={:?}", &CONFIG.log_format);
+    info!("Config: log_filter={}", log_level.filter());
+
+    // kube client, shared by all requests, fails fast when the API server can't be reached
+    let client = match build_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("failed to create the kube client: {e}");
+            std::process::exit(1);
+        }
+    };
+    match client.apiserver_version().await {
+        Ok(version) => info!("connected to the API server {}", version.git_version),
+        Err(e) => {
+            error!("failed to connect to the API server: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    // hosts cache
+    if CONFIG.watch_cache {
+        tokio::spawn(run_watcher(client.clone()));
+    }
+
+    // single writer of the hosts ConfigMap
+    let writer = spawn_writer(client.clone());
+
+    // heartbeat checked by /livez
+    tokio::spawn(run_heartbeat());
 
     // webhook
     let router_webhook = Router::new()
-        .hoop(alter_content_type)
+        .hoop(track_requests)
+        .hoop(affix_state::inject(client.clone()).inject(writer.clone()))
+        .hoop(negotiate)
         .get(get_root)
-        .push(Router::with_path("records").get(get_records).post(post_records))
+        .push(Router::with_path("records").get(get_records).post(post_records)
+            .push(Router::with_path("plan").post(post_plan)))
         .push(Router::with_path("adjustendpoints").post(post_adjustendpoints));
-    let service_webhook = Service::new(router_webhook)
-        .hoop(Logger::new());
-    let acceptor_webhook = TcpListener::new(&CONFIG.listen_addr)
-        .bind().await;
-    let server_webhook = Server::new(acceptor_webhook);
-    
+
     // health
-    let router_health = Router::new()
-        .push(Router::with_path("healthz").get(get_healthz));
-    let service_health = Service::new(router_health);
-    let acceptor_health = TcpListener::new(&CONFIG.health_listen_addr)
-        .bind().await;
-    let server_health = Server::new(acceptor_health);
+    let mut router_health = Router::new()
+        .hoop(affix_state::inject(client.clone()).inject(writer.clone()))
+        .push(Router::with_path("healthz").get(get_healthz))
+        .push(Router::with_path("livez").get(get_livez))
+        .push(Router::with_path("readyz").get(get_readyz))
+        .push(Router::with_path("metrics").get(get_metrics));
+
+    // admin
+    let router_admin = Router::new()
+        .hoop(affix_state::inject(client.clone()).inject(writer.clone()).inject(log_level))
+        .push(admin::router());
+
+    // openapi, generated from the routers above
+    set_namer(FlexNamer::new().short_mode(true));
+    let mut openapi = OpenApi::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+        .merge_router(&router_health)
+        .add_security_scheme("bearer", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)))
+        .merge(webhook_openapi(OpenApi::new("webhook", env!("CARGO_PKG_VERSION")).merge_router(&router_webhook)));
+    if CONFIG.admin_listen_addr.is_some() {
+        openapi = openapi.merge_router(&router_admin);
+    }
+
+    // servers, the openapi listener falls back to the health one, the admin
+    // routes are only served on their own listener
+    let mut servers: Vec<(Server<TcpAcceptor>, Service)> = Vec::new();
+    if let Some(addr) = &CONFIG.admin_listen_addr {
+        servers.push((bind(addr).await, Service::new(router_admin)));
+    }
+    match &CONFIG.openapi_listen_addr {
+        Some(addr) => servers.push((bind(addr).await, Service::new(openapi.into_router("openapi.json")))),
+        None => router_health = router_health.push(openapi.into_router("openapi.json")),
+    }
+    servers.push((bind(&CONFIG.listen_addr).await, Service::new(router_webhook).hoop(trace_requests).hoop(Logger::new())));
+    servers.push((bind(&CONFIG.health_listen_addr).await, Service::new(router_health)));
 
     // handle shutdown
-    let mut handles: Vec<ServerHandle> = Vec::new();
-    handles.push(server_webhook.handle());
-    handles.push(server_health.handle());
+    let handles: Vec<ServerHandle> = servers.iter().map(|(server, _)| server.handle()).collect();
     tokio::spawn(listen_shutdown_signal(handles));
 
     // start servers
-    let task_webhook = task::spawn(async move {server_webhook.serve(service_webhook).await;});
-    let task_health = task::spawn(async move {server_health.serve(service_health).await;});
-    task_webhook.await.unwrap();
-    task_health.await.unwrap();
+    let tasks: Vec<_> = servers.into_iter()
+        .map(|(server, service)| task::spawn(async move {server.serve(service).await;}))
+        .collect();
+    for task in join_all(tasks).await {
+        task.unwrap();
+    }
+    // publish the event occurrences still counted
+    writer.close().await;
+}
+
+async fn bind(addr: &str) -> Server<TcpAcceptor> {
+    Server::new(TcpListener::new(addr).bind().await)
 }
 
 async fn listen_shutdown_signal(handles: Vec<ServerHandle>) {
@@ -135,6 +272,6 @@ async fn listen_shutdown_signal(handles: Vec<ServerHandle>) {
         handle.stop_graceful(Duration::from_secs(60*5));
     }
 
-    let tasks: Vec<_> = handles.iter().map(|h| async_stop(h)).collect();
+    let tasks: Vec<_> = handles.iter().map(async_stop).collect();
     _ = join_all(tasks).await;
 }
@@ -0,0 +1,174 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::net::IpAddr;
+use std::time::Instant;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use salvo::prelude::*;
+use tower::{Layer, Service};
+
+use crate::changes::ChangeReport;
+use crate::hosts::HostRecords;
+
+// Routes of the webhook, other paths are counted as "other"
+const WEBHOOK_ROUTES: &[&str] = &["/", "/records", "/records/plan", "/adjustendpoints"];
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "host_webhook_http_requests_total", "Webhook requests by route, method and status", &["route", "method", "status"]
+).unwrap());
+static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "host_webhook_http_request_duration_seconds", "Webhook request latency by route and method", &["route", "method"]
+).unwrap());
+static KUBE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "host_webhook_kube_requests_total", "Kubernetes API requests by verb and status code, `error` when no response", &["verb", "code"]
+).unwrap());
+static KUBE_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "host_webhook_kube_request_duration_seconds", "Kubernetes API latency until the response headers by verb", &["verb"]
+).unwrap());
+static RECORDS: Lazy<IntGaugeVec> = Lazy::new(|| register_int_gauge_vec!(
+    "host_webhook_records", "Stored addresses by record type and domain", &["type", "domain"]
+).unwrap());
+static WRITES: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "host_webhook_writes_total", "Writes of the hosts by result", &["result"]
+).unwrap());
+static HISTORY_WRITES: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "host_webhook_history_writes_total", "Writes of the hosts revisions by result", &["result"]
+).unwrap());
+static LAST_WRITE: Lazy<IntGauge> = Lazy::new(|| register_int_gauge!(
+    "host_webhook_last_write_timestamp_seconds", "Time of the last successful write of the hosts"
+).unwrap());
+static SKIPPED_LINES: Lazy<IntGauge> = Lazy::new(|| register_int_gauge!(
+    "host_webhook_hosts_lines_skipped", "Hosts lines skipped by the parser in the last read of the hosts"
+).unwrap());
+static CHANGES: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "host_webhook_changes_total", "Record changes applied by operation", &["operation"]
+).unwrap());
+
+// Replace the records gauge by the stored records
+pub fn observe_records(records: &HostRecords) {
+    RECORDS.reset();
+    for (name, ips) in records {
+        let domain = name.split_once('.').map_or(name.as_str(), |(_, domain)| domain);
+        for ip in ips {
+            let type_ = match ip.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => "A",
+                Ok(IpAddr::V6(_)) => "AAAA",
+                Err(_) => "invalid",
+            };
+            RECORDS.with_label_values(&[type_, domain]).inc();
+        }
+    }
+}
+
+pub fn observe_write(success: bool) {
+    WRITES.with_label_values(&[if success { "success" } else { "failure" }]).inc();
+    if success {
+        LAST_WRITE.set(Utc::now().timestamp());
+    }
+}
+
+pub fn observe_history_write(success: bool) {
+    HISTORY_WRITES.with_label_values(&[if success { "success" } else { "failure" }]).inc();
+}
+
+pub fn observe_skipped_lines(count: usize) {
+    SKIPPED_LINES.set(count as i64);
+}
+
+pub fn observe_changes(report: &ChangeReport) {
+    CHANGES.with_label_values(&["create"]).inc_by(report.created.len() as u64);
+    CHANGES.with_label_values(&["update"]).inc_by(report.updated.len() as u64);
+    CHANGES.with_label_values(&["delete"]).inc_by(report.deleted.len() as u64);
+    CHANGES.with_label_values(&["skip"]).inc_by(report.skipped.len() as u64);
+}
+
+// Webhook route of a request path, "other" when unknown. The router ignores
+// empty segments, so does the route.
+pub fn route_of(path: &str) -> &'static str {
+    let path = format!("/{}", path.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("/"));
+    WEBHOOK_ROUTES.iter().find(|route| **route == path).copied().unwrap_or("other")
+}
+
+// Count the webhook requests and their latency
+#[handler]
+pub async fn track_requests(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let started = Instant::now();
+    let route = route_of(req.uri().path());
+    let method = req.method().to_string();
+    ctrl.call_next(req, depot, res).await;
+    let status = res.status_code.unwrap_or(StatusCode::OK);
+    HTTP_REQUESTS.with_label_values(&[route, &method, status.as_str()]).inc();
+    HTTP_DURATION.with_label_values(&[route, &method]).observe(started.elapsed().as_secs_f64());
+}
+
+/// Metrics in the Prometheus text format
+#[endpoint(
+    tags("health"),
+    responses((status_code = 200, description = "Prometheus metrics")),
+)]
+pub async fn get_metrics(res: &mut Response) {
+    let mut buffer = Vec::new();
+    match TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => {
+            res.add_header("content-type", TextEncoder::new().format_type(), true).ok();
+            res.write_body(buffer).ok();
+        }
+        Err(e) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Text::Plain(e.to_string()));
+        }
+    }
+}
+
+// Tower layer of the kube client counting the API requests
+#[derive(Clone, Copy, Default)]
+pub struct KubeMetricsLayer;
+
+impl<S> Layer<S> for KubeMetricsLayer {
+    type Service = KubeMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KubeMetrics { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct KubeMetrics<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<http::Request<B>> for KubeMetrics<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let watch = req.uri().query().is_some_and(|query| query.split('&').any(|p| p == "watch=true"));
+        let verb = if watch { "WATCH".to_string() } else { req.method().to_string() };
+        let started = Instant::now();
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await;
+            let code = match &response {
+                Ok(response) => response.status().as_str().to_string(),
+                Err(_) => "error".to_string(),
+            };
+            KUBE_REQUESTS.with_label_values(&[&verb, &code]).inc();
+            KUBE_DURATION.with_label_values(&[&verb]).observe(started.elapsed().as_secs_f64());
+            response
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use salvo::http::mime::{self, Mime};
+use salvo::http::header::{ACCEPT, CONTENT_TYPE};
+use salvo::http::{HeaderValue, Method, ParseError};
+use salvo::oapi::{OpenApi, RefOr};
+use salvo::prelude::*;
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+use crate::error::Error;
+
+// Media type defined by the external-dns webhook provider specification
+pub const WEBHOOK_MEDIA_TYPE: &str = "application/external.dns.webhook+json;version=1";
+const WEBHOOK_SUBTYPE: &str = "external.dns.webhook";
+const WEBHOOK_VERSION: &str = "1";
+
+// Check that a media type is the webhook one, with a supported version parameter.
+// `version_required` is false for Accept media ranges, where the parameter is optional.
+fn is_webhook_media_type(mime: &Mime, version_required: bool) -> bool {
+    if mime.type_() != mime::APPLICATION
+        || mime.subtype() != WEBHOOK_SUBTYPE
+        || mime.suffix() != Some(mime::JSON) {
+        return false;
+    }
+    match mime.get_param("version") {
+        Some(v) => v.as_str() == WEBHOOK_VERSION,
+        None => !version_required,
+    }
+}
+
+// Quality factor of a media range, defaults to 1 when absent or malformed
+fn quality(mime: &Mime) -> f32 {
+    mime.get_param("q")
+        .and_then(|q| q.as_str().parse::<f32>().ok())
+        .unwrap_or(1.0)
+}
+
+// Check whether one media range of an Accept header matches the webhook media type
+fn accepts_webhook(range: &Mime) -> bool {
+    if quality(range) <= 0.0 {
+        return false;
+    }
+    if range.type_() == mime::STAR {
+        return true;
+    }
+    if range.type_() != mime::APPLICATION {
+        return false;
+    }
+    if range.subtype() == mime::STAR {
+        return true;
+    }
+    is_webhook_media_type(range, false)
+}
+
+// Return true when the Accept header is missing or allows the webhook media type
+pub fn is_acceptable(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return true;
+    };
+    if accept.trim().is_empty() {
+        return true;
+    }
+    accept.split(',')
+        .filter_map(|range| range.trim().parse::<Mime>().ok())
+        .any(|range| accepts_webhook(&range))
+}
+
+// Return true when the Content-Type header is the webhook media type with a supported version
+pub fn is_supported_content_type(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|v| v.trim().parse::<Mime>().ok())
+        .is_some_and(|mime| is_webhook_media_type(&mime, true))
+}
+
+// Enforce the webhook media type on requests and responses:
+// - 406 when the Accept header excludes the webhook media type
+// - 415 when a request body isn't sent as the webhook media type
+#[handler]
+pub async fn negotiate(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+    let content_type = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    if !is_acceptable(accept) {
+        debug!("unsupported Accept header: {:?}", accept);
+        res.render(Error::NotAcceptable(WEBHOOK_MEDIA_TYPE));
+        ctrl.skip_rest();
+    } else if req.method() == Method::POST && !is_supported_content_type(content_type) {
+        debug!("unsupported Content-Type header: {:?}", content_type);
+        res.render(Error::UnsupportedMediaType(WEBHOOK_MEDIA_TYPE));
+        ctrl.skip_rest();
+    } else {
+        ctrl.call_next(req, depot, res).await;
+    }
+
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(WEBHOOK_MEDIA_TYPE));
+}
+
+// Document every JSON response of the webhook routes with the webhook media type,
+// matching what `negotiate` sends on the wire
+pub fn webhook_openapi(mut openapi: OpenApi) -> OpenApi {
+    for path in openapi.paths.values_mut() {
+        for operation in path.operations.values_mut() {
+            for response in operation.responses.values_mut() {
+                if let RefOr::Type(response) = response {
+                    if let Some(content) = response.contents.shift_remove("application/json") {
+                        response.contents.insert(WEBHOOK_MEDIA_TYPE.to_string(), content);
+                    }
+                }
+            }
+        }
+    }
+    openapi
+}
+
+// Parse a request body sent as the webhook media type.
+// `Request::parse_json` only accepts a `json` subtype, so the body is decoded directly.
+pub async fn parse_webhook_json<T: DeserializeOwned>(req: &mut Request) -> Result<T, ParseError> {
+    let payload = req.payload().await?;
+    serde_json::from_slice::<T>(payload).map_err(ParseError::SerdeJson)
+}
@@ -1,12 +1,20 @@
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, info, warn};
 use core::str;
 use std::collections::HashMap;
 
-use crate::{config::CONFIG, hosts::{read_host, write_host}};
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::adjust::adjust_endpoints;
+use crate::changes::{record_type_of, ChangeReport};
+use crate::client::obtain_client;
+use crate::config::CONFIG;
+use crate::dryrun::{dry_run, plan_changes, DryRun, Plan, LAST_DRY_RUN};
+use crate::error::{Error, Result};
+use crate::hosts::{current_host, HostRecords, SUPPORTED_RECORD_TYPES};
+use crate::negotiation::{parse_webhook_json, WEBHOOK_MEDIA_TYPE};
+use crate::writer::{obtain_writer, Mutation};
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum RecordType {
     A,
     AAAA,
@@ -19,7 +27,7 @@ pub enum RecordType {
     NAPTR
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 pub struct ProviderSpecificProperty {
     pub name: String,
 	pub value: String,
@@ -30,113 +38,107 @@ pub type ProviderSpecific = Vec<ProviderSpecificProperty>;
 pub type Targets = Vec<String>;
 pub type Labels = HashMap<String,String>;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Endpoint {
-	// The hostname of the DNS record
+	/// The hostname of the DNS record
 	pub dns_name: String,
-	// The targets the DNS record points to
+	/// The targets the DNS record points to
     pub targets: Targets,
-	// RecordType type of record, e.g. CNAME, A, AAAA, SRV, TXT etc
+	/// RecordType type of record, e.g. CNAME, A, AAAA, SRV, TXT etc
 	pub record_type: RecordType,
-	// Identifier to distinguish multiple records with the same name and type (e.g. Route53 records with routing policies other than 'simple')
+	/// Identifier to distinguish multiple records with the same name and type (e.g. Route53 records with routing policies other than 'simple')
 	pub set_identifier: Option<String>,
-	// TTL for the record
+	/// TTL for the record
 	pub record_t_t_l: Option<TTL>,
-	// Labels stores labels defined for the Endpoint
+	/// Labels stores labels defined for the Endpoint
 	pub labels: Option<Labels>,
-	// ProviderSpecific stores provider specific config
+	/// ProviderSpecific stores provider specific config
 	pub provider_specific: Option<ProviderSpecific>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct Changes {
-	// Records that need to be created
+	/// Records that need to be created
 	pub create: Option<Records>,
-	// Records that need to be updated (current data)
+	/// Records that need to be updated (current data)
 	pub update_old: Option<Records>,
-	// Records that need to be updated (desired data)
+	/// Records that need to be updated (desired data)
 	pub update_new: Option<Records>,
-	// Records that need to be deleted
+	/// Records that need to be deleted
 	pub delete: Option<Records>,
 }
 
 pub type Records = Vec<Endpoint>;
 
-#[handler]
-pub async fn get_records(req: &mut Request, res: &mut Response) {
-    // Variable à retourner
-    let mut entrypoints: Vec<Endpoint> = Vec::new();
-    let records = match read_host().await {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Erreur de récupération des données {e}");
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Text::Plain(format!("Erreur de récupération des données {e}")));
-            return;
-        }
-    };
+/// Return the records stored in the hosts ConfigMap
+#[endpoint(
+    tags("webhook"),
+    status_codes(200, 404, 406, 502),
+)]
+pub async fn get_records(depot: &mut Depot) -> Result<Json<Records>> {
+    let snapshot = current_host(&obtain_client(depot)).await?;
+    Ok(Json(endpoints(&snapshot.records)))
+}
 
+// Convert stored records to one endpoint per name and record type
+pub fn endpoints(records: &HostRecords) -> Records {
+    let mut endpoints: Records = Vec::new();
     for (name, ips) in records {
-        if CONFIG.debug {
-            let mut msg = String::from("return record: ");
-            msg += &name.clone();
-            msg += " ";
-            let mut first = true;
-            for ip in &ips {
-                if first { 
-                    first = false; 
-                } else {
-                    msg += ",";
-                }
-                msg += &ip.clone();
+        for record_type in SUPPORTED_RECORD_TYPES {
+            let targets: Targets = ips.iter()
+                .filter(|ip| record_type_of(ip) == *record_type)
+                .cloned()
+                .collect();
+            if targets.is_empty() {
+                continue;
             }
-            debug!(msg);
+            debug!("return record: {name} {record_type:?} {}", targets.join(","));
+            endpoints.push(Endpoint {
+                dns_name: name.clone(),
+                record_type: *record_type,
+                targets,
+                set_identifier: None,
+                record_t_t_l: None,
+                labels: None,
+                provider_specific: None
+            })
         }
-        entrypoints.push(Endpoint {
-            dns_name: name.clone(),
-            record_type: RecordType::A,
-            targets: ips.into_iter().collect(),
-            set_identifier: None,
-            record_t_t_l: None,
-            labels: None,
-            provider_specific: None
-        })
     }
+    endpoints
+}
 
-    // Convertit les enregistrements en JSON et les envoie dans la réponse
-    match serde_json::to_string(&entrypoints) {
-        Ok(json) => {
-            res.status_code(StatusCode::OK);
-            res.render(Text::Json(json));
-        }
-        Err(e) => {
-            eprintln!("Erreur lors de la conversion en JSON: {}", e);
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Text::Plain("Erreur lors de la conversion en JSON"));
-            return;
-        }
+// Reject changes on names outside of the configured domain filter
+pub(crate) fn check_domain_filter(changes: &Changes) -> Result<()> {
+    let names: Vec<String> = [&changes.create, &changes.update_old, &changes.update_new, &changes.delete]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|endpoint| !CONFIG.domain_filter.matches(&endpoint.dns_name))
+        .map(|endpoint| endpoint.dns_name.clone())
+        .collect();
+    if names.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Filter { names })
     }
-
-    // Set Content-Type Header with Accept Header
-    if let Some(v) = req.header("Accept") {
-        let accept_header_value: String = v;
-        if let Err(err) = res.add_header("Content-Type", accept_header_value, true) {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Text::Plain(format!("Failed to add header Content-Type: {}",err.to_string())));
-            return;
-        };
-    };
 }
 
-#[handler]
-pub async fn post_records(req: &mut Request, res: &mut Response) {
-    // Récupérer le corps de la requête en tant que JSON
-    let changes: Changes = match req.parse_json().await {
-        Ok(records) => records,
+/// Apply a set of changes to the hosts ConfigMap
+#[endpoint(
+    tags("webhook"),
+    request_body(content = Changes, content_type = WEBHOOK_MEDIA_TYPE),
+    responses(
+        (status_code = 204, description = "Changes applied"),
+        (status_code = 200, description = "Changes evaluated in dry-run, nothing written", body = DryRun),
+    ),
+    status_codes(200, 204, 400, 403, 404, 406, 409, 415, 422, 502, 503),
+)]
+pub async fn post_records(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<()> {
+    let changes: Changes = match parse_webhook_json(req).await {
+        Ok(changes) => changes,
         Err(e) => {
-            warn!("ParseError: {e}");
             match req.payload().await {
                 Ok(b) => { match str::from_utf8(b) {
                         Ok(s) => {warn!("body: {s}");}
@@ -144,179 +146,81 @@ pub async fn post_records(req: &mut Request, res: &mut Response) {
                     }}
                 Err(e) => {warn!("get body {e}");}
             };
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Text::Plain("Invalid JSON input"));
-            return;
+            return Err(e.into());
         }
     };
-    if CONFIG.debug {
-        if let Some(r) = &changes.create {
-            debug!("in create records: {:?}", r);
-        }
-        if let Some(r) = &changes.delete {
-            debug!("in delete records: {:?}", r);
-        }
-        if let Some(r) = &changes.update_new {
-            debug!("in update new records: {:?}", r);
-        }
-        if let Some(r) = &changes.update_old {
-            debug!("in update old records: {:?}", r);
-        }
+    if let Some(r) = &changes.create {
+        debug!("in create records: {:?}", r);
     }
-
-    if !CONFIG.dry_run {
-        let mut host_records= match read_host().await {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Erreur de récupération des données {e}");
-                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-                res.render(Text::Plain(format!("Erreur de récupération des données {e}")));
-                return;
-            }
-        };
-
-        // Add create items to records
-        if let Some(records) = &changes.create {
-            for record in records {
-                if let Some(e) = host_records.insert(
-                                        record.dns_name.clone(),
-                                        record.targets.clone().into_iter().collect()) {
-                    warn!("create replaced: {e:?}");
-                }
-            }
-        }
-        // Remove delete items
-        if let Some(records) = &changes.delete {
-            for record in records {
-                match host_records.remove(&record.dns_name) {
-                    Some(v) => { 
-                        if CONFIG.debug {
-                            let ips: Vec<String> = v.into_iter().collect();
-                            debug!("removed {} -> {}", record.dns_name, ips.join(",")); }}
-                    None => { warn!("delete {} isn't in hosts", record.dns_name); }
-                }
-            }
-        }
-        // Replace from update_old by update_new
-        if let Some(new_records) = &changes.update_new {
-            if let Some(old_records) = &changes.update_old {
-                let mut old_record_iter = old_records.iter();
-                for new_record in new_records {
-                   if let Some(old_record) = old_record_iter.next() {
-                        if old_record.dns_name != new_record.dns_name {
-                            warn!("skip replace for records {:?} -> {:?}", old_record, new_record);
-                            continue;
-                        }
-                        match host_records.get_mut(&old_record.dns_name) {
-                            Some(ips) => {
-                                ips.retain(|ip| !old_record.targets.contains(&ip));
-                                for ip in &new_record.targets {
-                                    if !ips.insert(ip.clone()) {
-                                        warn!("hosts {} all_ready contains {ip}", new_record.dns_name);
-                                    }
-                                }
-                            }
-                            None => { warn!("replace {} isn't in hosts", &old_record.dns_name);
-                            if let Some(e) = host_records.insert(
-                                new_record.dns_name.clone(),
-                                new_record.targets.clone().into_iter().collect()) {
-                                    warn!("cannot add {new_record:?} : {e:?}");
-                                }
-                            }
-                        }
-                    } else {
-                        warn!("Cannot iterate on old_records");
-                    }
-                }
-            } else {
-                warn!("No changes.OldRecords and Some(changes.NewRecords)");
-            }
-        } else if let Some(_) = &changes.update_old {
-            warn!("No changes.NewRecords and Some(changes.OldRecords)");
-        }
-        // Finaly replace hosts
-        if let Err(e) = write_host(&host_records).await {
-            error!("Failed to write host file : {e}");
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Text::Plain(format!("Failed to patch configmap : {e}")));
-            return;
-        }
+    if let Some(r) = &changes.delete {
+        debug!("in delete records: {:?}", r);
     }
-
-    // Set Content-Type Header with Accept Header
-    if let Some(v) = req.header("Accept") {
-        let accept_header_value: String = v;
-        if let Err(err) = res.add_header("Content-Type", accept_header_value, true) {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Text::Plain(format!("Failed to add header Content-Type: {}",err.to_string())));
-            return;
-        };
-    };
+    if let Some(r) = &changes.update_new {
+        debug!("in update new records: {:?}", r);
+    }
+    if let Some(r) = &changes.update_old {
+        debug!("in update old records: {:?}", r);
+    }
+    check_domain_filter(&changes)?;
+
+    if CONFIG.dry_run {
+        let plan = dry_run(&obtain_client(depot), &changes).await?;
+        log_report(&plan.changes, "dry-run ");
+        if !plan.diff.is_empty() {
+            info!("dry-run diff:\n{}", plan.diff);
+        }
+        *LAST_DRY_RUN.write().unwrap() = Some(plan.clone());
+        res.status_code(StatusCode::OK);
+        res.render(Json(plan));
+        return Ok(());
+    }
+    let report = obtain_writer(depot).submit(Mutation::Apply(changes)).await?;
+    log_report(&report, "");
     res.status_code(StatusCode::NO_CONTENT);
+    Ok(())
 }
 
-
-#[handler]
-pub async fn post_adjustendpoints(req: &mut Request, res: &mut Response) {
-    let mut records: Records = match req.parse_json().await {
-        Ok(records) => records,
-        Err(e) => {
-            info!("Impossible de lire le corps de la requête en tant que texte UTF-8 : {}.", e);
-            res.render(Text::Plain(e.to_string()));
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            return;
-        }
-    };
-    if CONFIG.debug {
-        for r in &records {
-            debug!("in record: {:?}", r);
-        }
+/// Preview the effect of a set of changes without writing them
+#[endpoint(
+    tags("webhook"),
+    request_body(content = Changes, content_type = WEBHOOK_MEDIA_TYPE),
+    status_codes(200, 400, 404, 406, 415, 502),
+)]
+pub async fn post_plan(req: &mut Request, depot: &mut Depot) -> Result<Json<Plan>> {
+    let changes: Changes = parse_webhook_json(req).await?;
+    let snapshot = current_host(&obtain_client(depot)).await?;
+    let mut plan = plan_changes(&snapshot, &changes, &CONFIG.change_policy);
+    if let Err(e) = check_domain_filter(&changes) {
+        // rejected before the changes are applied, like post_records does
+        let mut errors = vec![e.to_string()];
+        errors.append(&mut plan.errors);
+        plan = Plan { valid: false, errors, ..Default::default() };
     }
+    Ok(Json(plan))
+}
 
-    for record in &mut records {
-        record.set_identifier = None;
-        record.record_t_t_l = None;
-        record.labels = None;
-        record.provider_specific = None;
+pub(crate) fn log_report(report: &ChangeReport, prefix: &str) {
+    for change in &report.created {
+        info!("{prefix}create {} {:?} -> {}", change.name, change.record_type, change.after.join(","));
     }
-
-    if CONFIG.debug {
-        for r in &records {
-            debug!("out record: {:?}", r);
-        }
+    for change in &report.updated {
+        info!("{prefix}update {} {:?} {} -> {}", change.name, change.record_type, change.before.join(","), change.after.join(","));
     }
-
-    match serde_json::to_string(&records) {
-        Ok(json) => {
-            res.status_code(StatusCode::OK);
-            res.render(Text::Json(json));
-        }
-        Err(e) => {
-            eprintln!("Erreur lors de la conversion en JSON: {}", e);
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Text::Plain("Erreur lors de la conversion en JSON"));
-            return;
-        }
+    for change in &report.deleted {
+        info!("{prefix}delete {} {:?} {}", change.name, change.record_type, change.before.join(","));
     }
+    for skipped in &report.skipped {
+        warn!("{prefix}skip {} {:?}: {}", skipped.name, skipped.record_type, skipped.reason);
+    }
+}
 
-    // let body = match req.payload().await {
-    //     Ok(b) => { match str::from_utf8(b) {
-    //             Ok(s) => s.to_string(),
-    //             Err(e) => e.to_string(),
-    //         }}
-    //     Err(e) => e.to_string(),
-    // };
-    // debug!("set response: {body}");
-    // res.render(Text::Plain(body));
-    // res.status_code(StatusCode::OK);
-
-    // Set Content-Type Header with Accept Header
-    if let Some(v) = req.header("Accept") {
-        let accept_header_value: String = v;
-        if let Err(err) = res.add_header("Content-Type", accept_header_value, true) {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Text::Plain(format!("Failed to add header Content-Type: {}",err.to_string())));
-            return;
-        };
-    };
+/// Normalize endpoints and drop what the hosts store can't hold
+#[endpoint(
+    tags("webhook"),
+    request_body(content = Records, content_type = WEBHOOK_MEDIA_TYPE),
+    status_codes(200, 400, 406, 415),
+)]
+pub async fn post_adjustendpoints(req: &mut Request) -> Result<Json<Records>> {
+    let records: Records = parse_webhook_json(req).await?;
+    Ok(Json(adjust_endpoints(records)))
 }
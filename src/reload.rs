@@ -0,0 +1,88 @@
+use std::fmt;
+use std::str::FromStr;
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment};
+use kube::api::{Api, Patch, PatchParams};
+use kube::Client;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::config::CONFIG;
+use crate::hosts::host_namespace;
+
+// Pod template annotation set to the hosts checksum, changing it rolls the pods
+pub const POD_CHECKSUM_ANNOTATION: &str = "host-webhook-provider/hosts-checksum";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkloadKind {
+    Deployment,
+    DaemonSet,
+}
+
+// Workload restarted after each change of the hosts, `[namespace/]kind/name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    // Defaults to the namespace of the hosts ConfigMap
+    pub namespace: Option<String>,
+    pub kind: WorkloadKind,
+    pub name: String,
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        let (namespace, kind, name) = match parts[..] {
+            [kind, name] => (None, kind, name),
+            [namespace, kind, name] if !namespace.is_empty() => (Some(namespace.to_string()), kind, name),
+            _ => return Err(format!("expected [namespace/]kind/name, got {s:?}")),
+        };
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "deployment" | "deployments" | "deploy" => WorkloadKind::Deployment,
+            "daemonset" | "daemonsets" | "ds" => WorkloadKind::DaemonSet,
+            _ => return Err(format!("unsupported workload kind {kind:?}, expected deployment or daemonset")),
+        };
+        if name.is_empty() {
+            return Err(format!("missing workload name in {s:?}"));
+        }
+        Ok(Workload { namespace, kind, name: name.to_string() })
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WorkloadKind::Deployment => "deployment",
+            WorkloadKind::DaemonSet => "daemonset",
+        };
+        match &self.namespace {
+            Some(namespace) => write!(f, "{namespace}/{kind}/{}", self.name),
+            None => write!(f, "{kind}/{}", self.name),
+        }
+    }
+}
+
+// Merge patch of the pod template annotations. The pods only roll when the
+// checksum changes, the same content written again leaves them alone.
+pub fn restart_patch(checksum: &str) -> serde_json::Value {
+    json!({ "spec": { "template": { "metadata": { "annotations": { POD_CHECKSUM_ANNOTATION: checksum } } } } })
+}
+
+// Roll the configured workloads so that they load the new hosts, failures are only logged
+pub async fn restart_workloads(client: &Client, checksum: &str) {
+    let patch = restart_patch(checksum);
+    for workload in &CONFIG.restart_workloads {
+        let namespace = workload.namespace.as_deref().unwrap_or_else(|| host_namespace(client));
+        let params = PatchParams::default();
+        let patched = match workload.kind {
+            WorkloadKind::Deployment => Api::<Deployment>::namespaced(client.clone(), namespace)
+                .patch(&workload.name, &params, &Patch::Merge(&patch)).await.map(|_| ()),
+            WorkloadKind::DaemonSet => Api::<DaemonSet>::namespaced(client.clone(), namespace)
+                .patch(&workload.name, &params, &Patch::Merge(&patch)).await.map(|_| ()),
+        };
+        match patched {
+            Ok(()) => info!("restarting {workload} for hosts checksum {checksum}"),
+            Err(e) => warn!("failed to restart {workload}: {e}"),
+        }
+    }
+}
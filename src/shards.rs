@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hosts::{format_records, HostRecords};
+
+// Key of the hosts ConfigMap holding the manifest once the records are sharded
+pub const MANIFEST_KEY: &str = "shards.json";
+// Label of the shard ConfigMaps, set to the name of the hosts ConfigMap
+pub const SHARD_OF_LABEL: &str = "host-webhook-provider/shard-of";
+// Shards tried at most when names hash unevenly
+const MAX_SHARDS: usize = 256;
+
+// Where the records are stored once they don't fit in the hosts ConfigMap
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShardManifest {
+    // How names are assigned to shards, only "hash" for now
+    pub by: String,
+    // Hash of the whole hosts content, part of the shard names
+    pub generation: String,
+    // ConfigMaps holding the records, each one a hosts file under the hosts key
+    pub configmaps: Vec<String>,
+}
+
+// FNV-1a, stable across releases unlike the std hasher
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+pub fn shard_of(name: &str, count: usize) -> usize {
+    (fnv1a(name.as_bytes()) % count as u64) as usize
+}
+
+// Split records by name hash, every address of a name lands in the same shard
+pub fn split_records(records: &HostRecords, count: usize) -> Vec<HostRecords> {
+    let mut shards = vec![HostRecords::new(); count];
+    for (name, ips) in records {
+        shards[shard_of(name, count)].insert(name.clone(), ips.clone());
+    }
+    shards
+}
+
+// Split records so that each rendered shard fits in `threshold` bytes,
+// None when they fit unsplit or `threshold` is 0
+pub fn plan_shards(records: &HostRecords, threshold: usize) -> Option<Vec<HostRecords>> {
+    let size = format_records(records).len();
+    if threshold == 0 || size <= threshold {
+        return None;
+    }
+    let mut count = size.div_ceil(threshold);
+    loop {
+        let shards = split_records(records, count);
+        if count >= MAX_SHARDS || shards.iter().all(|shard| format_records(shard).len() <= threshold) {
+            return Some(shards);
+        }
+        count += 1;
+    }
+}
+
+// Names of the shard ConfigMaps of one generation. A new content gets new
+// ConfigMaps, so that readers never see shards of different writes.
+pub fn shard_names(base: &str, generation: &str, count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{base}-{generation}-{i}")).collect()
+}
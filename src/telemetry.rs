@@ -0,0 +1,89 @@
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, Context};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::resource::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use salvo::http::HeaderMap;
+use salvo::prelude::*;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::metrics::route_of;
+
+// Service name of the spans when neither OTEL_SERVICE_NAME nor OTEL_RESOURCE_ATTRIBUTES set one
+const UNKNOWN_SERVICE: &str = "unknown_service";
+
+// Whether the standard OTEL variables ask for the OTLP exporter: an OTLP endpoint
+// is set or OTEL_TRACES_EXPORTER names otlp, and OTEL_SDK_DISABLED isn't true
+pub fn otlp_enabled(var: impl Fn(&str) -> Option<String>) -> bool {
+    if var("OTEL_SDK_DISABLED").is_some_and(|v| v.trim().eq_ignore_ascii_case("true")) {
+        return false;
+    }
+    match var("OTEL_TRACES_EXPORTER") {
+        Some(exporters) => exporters.split(',').any(|e| e.trim() == "otlp"),
+        None => ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"].iter()
+            .any(|name| var(name).is_some_and(|v| !v.is_empty())),
+    }
+}
+
+// Provider exporting the spans over OTLP/HTTP, None when no exporter is configured.
+// The endpoint, headers, timeout and resource come from the OTEL variables.
+pub fn tracer_provider() -> Result<Option<SdkTracerProvider>, String> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if !otlp_enabled(|name| std::env::var(name).ok()) {
+        return Ok(None);
+    }
+    // without endpoint, the exporter sends to http://localhost:4318/v1/traces
+    let exporter = SpanExporter::builder().with_http().build().map_err(|e| format!("OTLP exporter: {e}"))?;
+    let mut resource = Resource::builder().build();
+    if resource.get(&"service.name".into()).is_some_and(|name| name.as_str() == UNKNOWN_SERVICE) {
+        resource = Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build();
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// Trace context of the caller from the W3C `traceparent` and `tracestate` headers
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+// Span of each webhook request, child of the trace context of external-dns
+#[handler]
+pub async fn trace_requests(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let route = route_of(req.uri().path());
+    let method = req.method().to_string();
+    let span = info_span!("webhook request",
+        otel.name = format!("{method} {route}"),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = method,
+        http.route = route,
+        http.response.status_code = Empty,
+    );
+    span.set_parent(extract_context(req.headers()));
+    ctrl.call_next(req, depot, res).instrument(span.clone()).await;
+    let status = res.status_code.unwrap_or(StatusCode::OK);
+    span.record("http.response.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use kube::Client;
+use salvo::Depot;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::Instant;
+use tracing::{debug, info_span, instrument, warn, Instrument, Span};
+
+use crate::changes::{apply_changes, replace_records, without_owned, ChangePolicy, ChangeReport};
+use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use crate::events::{EventRecorder, NORMAL, WARNING};
+use crate::history::{history_name, record_revision};
+use crate::hooks::{spawn_hooks, HookPayload, Hooks};
+use crate::hosts::{format_records_by, host_namespace, hosts_checksum, update_host, HostRecords, HostsSnapshot};
+use crate::metrics::{observe_changes, observe_history_write, observe_write};
+use crate::records::Changes;
+use crate::reload::restart_workloads;
+
+// Pending mutations the writer accepts before callers wait
+const QUEUE_SIZE: usize = 256;
+// Mutations written in one ConfigMap update at most
+const MAX_BATCH: usize = 64;
+
+// Modification of the records submitted to the writer
+#[derive(Debug, Clone)]
+pub enum Mutation {
+    // Apply an external-dns change set with the configured policy
+    Apply(Changes),
+    // Replace every record, `reason` is recorded in the history
+    Replace { records: HostRecords, reason: String },
+    // Add addresses to the records, `reason` is recorded in the history
+    Merge { records: HostRecords, reason: String },
+    // Set the addresses of one name from the admin API, pinned when `owned`
+    SetHost { name: String, addresses: Vec<String>, owned: bool },
+    // Remove one name from the admin API, owned or not
+    DeleteHost { name: String },
+}
+
+struct Submission {
+    mutation: Mutation,
+    reply: oneshot::Sender<Result<ChangeReport>>,
+    // Span of the caller, linked from the span of the write
+    span: Span,
+}
+
+// Handle to the single task writing the hosts ConfigMap
+#[derive(Clone)]
+pub struct Writer {
+    tx: mpsc::Sender<Submission>,
+    recorder: Option<EventRecorder>,
+    hooks: Option<Hooks>,
+    // Held by the writer task from a write until its follow-ups ran
+    writing: Arc<Mutex<()>>,
+}
+
+impl Writer {
+    // Queue a mutation and wait for the write that includes it
+    pub async fn submit(&self, mutation: Mutation) -> Result<ChangeReport> {
+        let (reply, rx) = oneshot::channel();
+        self.tx.send(Submission { mutation, reply, span: Span::current() }).await
+            .map_err(|_| Error::Unavailable("hosts writer stopped".into()))?;
+        rx.await.map_err(|_| Error::Unavailable("hosts writer dropped the change".into()))?
+    }
+
+    // False once the writer task ended
+    pub fn is_running(&self) -> bool {
+        !self.tx.is_closed()
+    }
+
+    // Wait for the follow-ups of the writes and publish the pending events,
+    // before the process exits
+    pub async fn close(&self) {
+        drop(self.writing.lock().await);
+        if let Some(hooks) = &self.hooks {
+            hooks.close().await;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.close().await;
+        }
+    }
+}
+
+// Start the writer task, every mutation of the records must go through it
+pub fn spawn_writer(client: Client) -> Writer {
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let recorder = CONFIG.events.then(|| EventRecorder::spawn(&client));
+    let hooks = spawn_hooks();
+    let writing = Arc::new(Mutex::new(()));
+    tokio::spawn(run_writer(client, recorder.clone(), hooks.clone(), writing.clone(), rx));
+    Writer { tx, recorder, hooks, writing }
+}
+
+// Writer injected in the depot at startup
+pub fn obtain_writer(depot: &Depot) -> Writer {
+    depot.obtain::<Writer>()
+        .expect("hosts writer isn't injected in the depot")
+        .clone()
+}
+
+// Apply mutations in order on the records. A failing mutation gets its error
+// and leaves the records as they were, the following ones still apply.
+#[instrument(skip_all, fields(mutations = mutations.len()))]
+pub fn apply_batch(current: &HostsSnapshot, mutations: &[Mutation], policy: &ChangePolicy) -> (HostsSnapshot, Vec<Result<ChangeReport>>) {
+    let mut snapshot = current.clone();
+    let mut results = Vec::with_capacity(mutations.len());
+    for mutation in mutations {
+        results.push(apply_mutation(&snapshot, mutation, policy).map(|(next, report)| {
+            snapshot = next;
+            report
+        }));
+    }
+    (snapshot, results)
+}
+
+fn apply_mutation(snapshot: &HostsSnapshot, mutation: &Mutation, policy: &ChangePolicy) -> Result<(HostsSnapshot, ChangeReport)> {
+    let current = &snapshot.records;
+    let mut next = snapshot.clone();
+    let report = match mutation {
+        Mutation::Apply(changes) => {
+            let (changes, skipped) = without_owned(changes, &snapshot.owned);
+            let (records, mut report) = apply_changes(current, &changes, policy)?;
+            report.skipped.extend(skipped);
+            next.records = records;
+            report
+        }
+        Mutation::Replace { records, .. } => {
+            next.records = records.clone();
+            replace_records(current, records)
+        }
+        Mutation::Merge { records, .. } => {
+            for (name, ips) in records {
+                next.records.entry(name.clone()).or_default().extend(ips.iter().cloned());
+            }
+            replace_records(current, &next.records)
+        }
+        Mutation::SetHost { name, addresses, owned } => {
+            next.records.insert(name.clone(), addresses.iter().cloned().collect());
+            if *owned {
+                next.owned.insert(name.clone());
+            } else {
+                next.owned.remove(name);
+            }
+            replace_records(current, &next.records)
+        }
+        Mutation::DeleteHost { name } => {
+            if next.records.remove(name).is_none() && !next.owned.contains(name) {
+                return Err(Error::NotFound(format!("host {name}")));
+            }
+            next.owned.remove(name);
+            replace_records(current, &next.records)
+        }
+    };
+    Ok((next, report))
+}
+
+// Names created, updated and deleted by the mutations of one write
+fn changed_names(results: &[Result<ChangeReport>]) -> [BTreeSet<String>; 3] {
+    let (mut created, mut updated, mut deleted) = (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+    for report in results.iter().flatten() {
+        created.extend(report.created.iter().map(|c| c.name.clone()));
+        updated.extend(report.updated.iter().map(|c| c.name.clone()));
+        deleted.extend(report.deleted.iter().map(|c| c.name.clone()));
+    }
+    [created, updated, deleted]
+}
+
+// Summary of the names touched by the mutations of one write, None when nothing changed
+fn summary(mutations: &[Mutation], results: &[Result<ChangeReport>]) -> Option<String> {
+    let [created, updated, deleted] = changed_names(results);
+    if created.is_empty() && updated.is_empty() && deleted.is_empty() {
+        return None;
+    }
+    let mut reasons: Vec<&str> = mutations.iter().zip(results)
+        .filter_map(|(mutation, result)| match (mutation, result) {
+            (Mutation::Replace { reason, .. } | Mutation::Merge { reason, .. }, Ok(_)) => Some(reason.as_str()),
+            _ => None,
+        })
+        .collect();
+    let counts = format!("hosts updated: {} created, {} updated, {} deleted names", created.len(), updated.len(), deleted.len());
+    reasons.push(&counts);
+    Some(reasons.join("; "))
+}
+
+async fn run_writer(client: Client, recorder: Option<EventRecorder>, hooks: Option<Hooks>,
+    writing: Arc<Mutex<()>>, mut rx: mpsc::Receiver<Submission>) {
+    let window = Duration::from_millis(CONFIG.write_coalesce_ms);
+    while let Some(first) = rx.recv().await {
+        // taken before the callers are answered, released once the follow-ups ran
+        let _writing = writing.lock().await;
+        // collect what arrives within the window after the first mutation
+        let mut batch = vec![first];
+        let deadline = Instant::now() + window;
+        while batch.len() < MAX_BATCH {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(submission)) => batch.push(submission),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        debug!("writing {} coalesced mutations", batch.len());
+        // the write serves several requests, it follows from their spans
+        let span = info_span!("write hosts", mutations = batch.len());
+        for submission in &batch {
+            span.follows_from(&submission.span);
+        }
+        write_batch(&client, recorder.as_ref(), hooks.as_ref(), batch).instrument(span).await;
+    }
+}
+
+// Write the mutations of a batch, answer the callers, then record the change and
+// run its follow-ups
+async fn write_batch(client: &Client, recorder: Option<&EventRecorder>, hooks: Option<&Hooks>, batch: Vec<Submission>) {
+    let (mutations, replies): (Vec<Mutation>, Vec<_>) = batch.into_iter()
+        .map(|s| (s.mutation, s.reply))
+        .unzip();
+    let written = update_host(client, |snapshot| {
+        let (after, results) = apply_batch(snapshot, &mutations, &CONFIG.change_policy);
+        Ok((after.clone(), (snapshot.records.clone(), after.records, results)))
+    }).await;
+    match written {
+        Ok((before, after, results)) => {
+            // re-created records with the same addresses are reported but not written
+            let written = before != after;
+            if written {
+                results.iter().flatten().for_each(observe_changes);
+            }
+            let message = if written { summary(&mutations, &results) } else { None };
+            let [created, updated, deleted] = changed_names(&results);
+            // the callers don't wait for the follow-ups of the write
+            for (reply, result) in replies.into_iter().zip(results) {
+                // the caller may have gone away, nothing to do then
+                _ = reply.send(result);
+            }
+            let Some(message) = message else {
+                return;
+            };
+            if CONFIG.history_limit > 0 {
+                let recorded = record_revision(client, &before, &after, message.clone()).await;
+                observe_history_write(recorded.is_ok());
+                if let Err(e) = recorded {
+                    warn!("failed to record the hosts revision in {}: {e}", history_name());
+                    if let Some(recorder) = recorder {
+                        recorder.emit(WARNING, "HistoryWriteFailed", format!("failed to record the hosts revision: {e}"));
+                    }
+                }
+            }
+            if let Some(recorder) = recorder {
+                recorder.emit(NORMAL, "HostsUpdated", message.clone());
+            }
+            let checksum = hosts_checksum(&format_records_by(&after, CONFIG.hosts_order));
+            if let Some(hooks) = hooks {
+                hooks.notify(HookPayload {
+                    configmap: CONFIG.host_configmap_name.clone(),
+                    namespace: host_namespace(client).to_string(),
+                    checksum: checksum.clone(),
+                    summary: message,
+                    created: created.into_iter().collect(),
+                    updated: updated.into_iter().collect(),
+                    deleted: deleted.into_iter().collect(),
+                });
+            }
+            if !CONFIG.restart_workloads.is_empty() {
+                restart_workloads(client, &checksum).await;
+            }
+        }
+        Err(e) => {
+            observe_write(false);
+            if let Some(recorder) = recorder {
+                recorder.emit(WARNING, "HostsUpdateFailed", format!("failed to write the hosts: {e}"));
+            }
+            let e = Arc::new(e);
+            for reply in replies {
+                _ = reply.send(Err(Error::Shared(e.clone())));
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use host_webhook_provider::adjust::{adjust_endpoint, adjust_endpoints};
+use host_webhook_provider::records::RecordType;
+
+mod common;
+use common::endpoint;
+
+#[test]
+fn names_are_normalized() {
+    let (adjusted, notes) = adjust_endpoint(endpoint(" A.Example.Local. ", RecordType::A, &["10.0.0.1"]));
+    assert_eq!(adjusted.unwrap().dns_name, "a.example.local");
+    assert_eq!(notes.len(), 1);
+    assert!(notes[0].starts_with("normalized name"));
+
+    let (adjusted, notes) = adjust_endpoint(endpoint("a.local", RecordType::A, &["10.0.0.1"]));
+    assert_eq!(adjusted.unwrap().dns_name, "a.local");
+    assert!(notes.is_empty());
+}
+
+#[test]
+fn invalid_names_are_dropped() {
+    let (adjusted, notes) = adjust_endpoint(endpoint("under_score.local", RecordType::A, &["10.0.0.1"]));
+    assert!(adjusted.is_none());
+    assert!(notes.last().unwrap().starts_with("dropped"));
+}
+
+#[test]
+fn ipv6_targets_are_deduplicated_and_canonicalized() {
+    let targets = ["2001:DB8:0:0:0:0:0:1", "2001:db8::1", "2001:db8::0002"];
+    let (adjusted, notes) = adjust_endpoint(endpoint("a.local", RecordType::AAAA, &targets));
+    assert_eq!(adjusted.unwrap().targets, vec!["2001:db8::1", "2001:db8::2"]);
+    assert!(notes.contains(&"canonicalized target \"2001:DB8:0:0:0:0:0:1\" -> \"2001:db8::1\"".to_string()));
+    assert!(notes.contains(&"removed duplicate target \"2001:db8::1\"".to_string()));
+}
+
+#[test]
+fn targets_must_match_the_record_type() {
+    let (adjusted, notes) = adjust_endpoint(endpoint("a.local", RecordType::A, &["2001:db8::1", "10.0.0.1"]));
+    assert_eq!(adjusted.unwrap().targets, vec!["10.0.0.1"]);
+    assert!(notes[0].starts_with("removed invalid target \"2001:db8::1\""));
+
+    let (adjusted, notes) = adjust_endpoint(endpoint("a.local", RecordType::A, &["2001:db8::1"]));
+    assert!(adjusted.is_none());
+    assert_eq!(notes.last().unwrap(), "dropped: no valid target");
+}
+
+#[test]
+fn unsupported_types_are_dropped() {
+    for record_type in [RecordType::CNAME, RecordType::TXT, RecordType::MX] {
+        let (adjusted, notes) = adjust_endpoint(endpoint("a.local", record_type, &["b.local"]));
+        assert!(adjusted.is_none());
+        assert!(notes[0].contains("isn't supported"));
+    }
+    let records = vec![
+        endpoint("a.local", RecordType::TXT, &["heritage=external-dns"]),
+        endpoint("a.local", RecordType::A, &["10.0.0.1"]),
+    ];
+    let adjusted = adjust_endpoints(records);
+    assert_eq!(adjusted.len(), 1);
+    assert_eq!(adjusted[0].record_type, RecordType::A);
+}
+
+#[test]
+fn provider_properties_are_removed() {
+    let mut with_properties = endpoint("a.local", RecordType::A, &["10.0.0.1"]);
+    with_properties.set_identifier = Some("eu".into());
+    with_properties.record_t_t_l = Some(300);
+    let (adjusted, notes) = adjust_endpoint(with_properties);
+    let adjusted = adjusted.unwrap();
+    assert_eq!((adjusted.set_identifier, adjusted.record_t_t_l), (None, None));
+    assert_eq!(notes, vec!["removed set identifier", "removed TTL"]);
+}
+
+#[test]
+fn endpoints_identical_once_normalized_are_merged() {
+    let records = vec![
+        endpoint("A.local.", RecordType::A, &["10.0.0.2"]),
+        endpoint("a.local", RecordType::A, &["10.0.0.1", "10.0.0.2"]),
+        endpoint("a.local", RecordType::AAAA, &["2001:db8::1"]),
+    ];
+    let adjusted = adjust_endpoints(records);
+    assert_eq!(adjusted.len(), 2);
+    assert_eq!((adjusted[0].dns_name.as_str(), adjusted[0].record_type), ("a.local", RecordType::A));
+    assert_eq!(adjusted[0].targets, vec!["10.0.0.1", "10.0.0.2"]);
+    assert_eq!(adjusted[1].record_type, RecordType::AAAA);
+}
@@ -0,0 +1,33 @@
+use host_webhook_provider::admin::search_hosts;
+use host_webhook_provider::hosts::HostsSnapshot;
+
+mod common;
+use common::{hosts, snapshot};
+
+fn pinned() -> HostsSnapshot {
+    let mut snapshot = snapshot(hosts(&[
+        ("b.local", &["10.0.0.2"]),
+        ("a.local", &["10.0.0.1", "2001:db8::1"]),
+        ("pinned.local", &["10.1.0.1"]),
+    ]));
+    snapshot.owned.insert("pinned.local".to_string());
+    snapshot
+}
+
+#[test]
+fn hosts_are_listed_by_name_with_ownership() {
+    let hosts = search_hosts(&pinned(), None);
+    let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+    assert_eq!(names, vec!["a.local", "b.local", "pinned.local"]);
+    assert_eq!(hosts[0].addresses, vec!["10.0.0.1", "2001:db8::1"]);
+    assert!(!hosts[0].owned);
+    assert!(hosts[2].owned);
+}
+
+#[test]
+fn search_matches_names_and_addresses() {
+    let by_name: Vec<String> = search_hosts(&pinned(), Some("PINNED")).into_iter().map(|h| h.name).collect();
+    assert_eq!(by_name, vec!["pinned.local"]);
+    let by_address: Vec<String> = search_hosts(&pinned(), Some("2001:db8")).into_iter().map(|h| h.name).collect();
+    assert_eq!(by_address, vec!["a.local"]);
+}
@@ -0,0 +1,99 @@
+use host_webhook_provider::cache::HostsCache;
+use host_webhook_provider::hosts::HostsSnapshot;
+
+mod common;
+use common::hosts;
+
+fn version(version: &str, address: &str) -> HostsSnapshot {
+    HostsSnapshot {
+        records: hosts(&[("a.local", &[address])]),
+        resource_version: Some(version.to_string()),
+        ..Default::default()
+    }
+}
+
+fn cached_version(cache: &HostsCache) -> Option<String> {
+    cache.snapshot().unwrap().resource_version
+}
+
+#[test]
+fn cache_is_empty_until_synced() {
+    let cache = HostsCache::default();
+    cache.store_watched(version("1", "10.0.0.1"), true);
+    assert!(cache.snapshot().is_none());
+    cache.mark_synced();
+    assert_eq!(cached_version(&cache).as_deref(), Some("1"));
+}
+
+#[test]
+fn watch_events_older_than_the_write_are_ignored() {
+    let cache = HostsCache::default();
+    cache.store_watched(version("1", "10.0.0.1"), true);
+    cache.mark_synced();
+
+    cache.store_written(version("3", "10.0.0.3"));
+    // the event of a concurrent write, delivered before the one of the provider write
+    assert!(!cache.store_watched(version("2", "10.0.0.2"), false));
+    assert_eq!(cached_version(&cache).as_deref(), Some("3"));
+
+    assert!(cache.store_watched(version("3", "10.0.0.3"), false));
+    // once the write is reported, every event is newer
+    assert!(cache.store_watched(version("4", "10.0.0.4"), false));
+    assert_eq!(cache.snapshot().unwrap().records, hosts(&[("a.local", &["10.0.0.4"])]));
+}
+
+#[test]
+fn relists_and_deletions_replace_the_written_records() {
+    let cache = HostsCache::default();
+    cache.mark_synced();
+    cache.store_written(version("3", "10.0.0.3"));
+    assert!(cache.store_watched(version("5", "10.0.0.5"), true));
+    assert_eq!(cached_version(&cache).as_deref(), Some("5"));
+
+    cache.store_written(version("6", "10.0.0.6"));
+    assert!(cache.store_watched(HostsSnapshot::default(), true));
+    assert!(cache.snapshot().unwrap().records.is_empty());
+}
+
+#[test]
+fn write_reported_by_the_watch_before_it_returns_is_not_stored() {
+    let cache = HostsCache::default();
+    cache.mark_synced();
+    assert!(cache.store_watched(version("3", "10.0.0.3"), false));
+    assert!(cache.store_watched(version("4", "10.0.0.4"), false));
+    // the write of version 3 returns after the watch reported it and a newer version
+    cache.store_written(version("3", "10.0.0.3"));
+    assert_eq!(cached_version(&cache).as_deref(), Some("4"));
+    // nothing is awaited, the next events apply
+    assert!(cache.store_watched(version("5", "10.0.0.5"), false));
+}
+
+#[test]
+fn sharded_records_are_served_for_their_generation() {
+    let cache = HostsCache::default();
+    let manifest = |version: &str, generation: &str| HostsSnapshot {
+        resource_version: Some(version.to_string()),
+        shards: vec![format!("hosts-{generation}-0")],
+        checksum: Some(generation.to_string()),
+        ..Default::default()
+    };
+    cache.store_watched(manifest("1", "g1"), true);
+    cache.mark_synced();
+    // the shards weren't read yet
+    assert!(cache.snapshot().is_none());
+
+    let read = HostsSnapshot { records: hosts(&[("a.local", &["10.0.0.1"])]), ..manifest("1", "g1") };
+    cache.store_shards(&read);
+    assert_eq!(cache.snapshot(), Some(read));
+
+    // a new generation needs its shards
+    assert!(cache.store_watched(manifest("2", "g2"), false));
+    assert!(cache.snapshot().is_none());
+
+    // the written records are the shards of the written generation
+    let written = HostsSnapshot { records: hosts(&[("b.local", &["10.0.0.2"])]), ..manifest("3", "g3") };
+    cache.store_written(written.clone());
+    assert_eq!(cache.snapshot(), Some(written.clone()));
+    assert!(cache.store_watched(manifest("3", "g3"), false));
+    assert_eq!(cache.snapshot(), Some(written));
+}
@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv6Addr;
+use host_webhook_provider::changes::{apply_changes, without_owned, ChangePolicy, CreateExisting, DeleteMissing, UpdateMismatch};
+use host_webhook_provider::error::Error;
+use host_webhook_provider::hosts::HostRecords;
+use host_webhook_provider::records::{Changes, Endpoint, RecordType};
+use proptest::prelude::*;
+
+mod common;
+use common::{endpoint, hosts};
+
+fn changes() -> Changes {
+    Changes { create: None, update_old: None, update_new: None, delete: None }
+}
+
+#[test]
+fn empty_changes_keep_records() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let (records, report) = apply_changes(&current, &changes(), &ChangePolicy::default()).unwrap();
+    assert_eq!(records, current);
+    assert!(report.is_empty());
+    assert!(report.skipped.is_empty());
+}
+
+#[test]
+fn create_adds_normalized_record() {
+    let changes = Changes {
+        create: Some(vec![endpoint("A.Local.", RecordType::A, &["10.0.0.2", "10.0.0.1", "10.0.0.1"])]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&HashMap::new(), &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.1", "10.0.0.2"])]));
+    assert_eq!(report.created.len(), 1);
+    assert_eq!(report.created[0].name, "a.local");
+    assert_eq!(report.created[0].after, vec!["10.0.0.1", "10.0.0.2"]);
+}
+
+#[test]
+fn create_keeps_other_address_family() {
+    let current = hosts(&[("a.local", &["2001:db8::1"])]);
+    let changes = Changes { create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]), ..changes() };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["2001:db8::1", "10.0.0.1"])]));
+    assert_eq!(report.created.len(), 1);
+}
+
+#[test]
+fn create_existing_replace() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes { create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]), ..changes() };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.2"])]));
+    assert_eq!(report.updated.len(), 1);
+    assert_eq!(report.updated[0].before, vec!["10.0.0.1"]);
+}
+
+#[test]
+fn create_existing_merge() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes { create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]), ..changes() };
+    let policy = ChangePolicy { on_create_existing: CreateExisting::Merge, ..Default::default() };
+    let (records, _) = apply_changes(&current, &changes, &policy).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.1", "10.0.0.2"])]));
+}
+
+#[test]
+fn create_existing_reject() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes { create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]), ..changes() };
+    let policy = ChangePolicy { on_create_existing: CreateExisting::Reject, ..Default::default() };
+    assert!(matches!(apply_changes(&current, &changes, &policy), Err(Error::Conflict(_))));
+}
+
+#[test]
+fn delete_removes_one_address_family() {
+    let current = hosts(&[("a.local", &["10.0.0.1", "2001:db8::1"]), ("b.local", &["10.0.0.2"])]);
+    let changes = Changes {
+        delete: Some(vec![
+            endpoint("a.local", RecordType::A, &["10.0.0.1"]),
+            endpoint("b.local", RecordType::A, &["10.0.0.2"]),
+        ]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["2001:db8::1"])]));
+    assert_eq!(report.deleted.len(), 2);
+}
+
+#[test]
+fn delete_missing_ignore() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes { delete: Some(vec![endpoint("b.local", RecordType::A, &["10.0.0.2"])]), ..changes() };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, current);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].name, "b.local");
+}
+
+#[test]
+fn delete_missing_reject() {
+    let changes = Changes { delete: Some(vec![endpoint("b.local", RecordType::A, &["10.0.0.2"])]), ..changes() };
+    let policy = ChangePolicy { on_delete_missing: DeleteMissing::Reject, ..Default::default() };
+    assert!(matches!(apply_changes(&HashMap::new(), &changes, &policy), Err(Error::Conflict(_))));
+}
+
+#[test]
+fn update_replaces_old_targets() {
+    let current = hosts(&[("a.local", &["10.0.0.1", "10.0.0.9"])]);
+    let changes = Changes {
+        update_old: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]),
+        update_new: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.2", "10.0.0.9"])]));
+    assert_eq!(report.updated.len(), 1);
+    assert_eq!(report.updated[0].after, vec!["10.0.0.2", "10.0.0.9"]);
+}
+
+#[test]
+fn update_missing_creates_record() {
+    let changes = Changes {
+        update_old: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]),
+        update_new: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&HashMap::new(), &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.2"])]));
+    assert_eq!(report.created.len(), 1);
+}
+
+#[test]
+fn update_mismatch_skip() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes {
+        update_old: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]),
+        update_new: Some(vec![
+            endpoint("b.local", RecordType::A, &["10.0.0.2"]),
+            endpoint("c.local", RecordType::A, &["10.0.0.3"]),
+        ]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, current);
+    assert_eq!(report.skipped.len(), 2);
+}
+
+#[test]
+fn update_mismatch_reject() {
+    let changes = Changes {
+        update_old: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]),
+        update_new: Some(vec![endpoint("a.local", RecordType::AAAA, &["2001:db8::1"])]),
+        ..changes()
+    };
+    let policy = ChangePolicy { on_update_mismatch: UpdateMismatch::Reject, ..Default::default() };
+    assert!(matches!(apply_changes(&HashMap::new(), &changes, &policy), Err(Error::Conflict(_))));
+}
+
+#[test]
+fn delete_then_create_in_one_set() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes {
+        create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.2"])]),
+        delete: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.1"])]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert_eq!(records, hosts(&[("a.local", &["10.0.0.2"])]));
+    assert_eq!(report.deleted.len(), 1);
+    assert_eq!(report.created.len(), 1);
+}
+
+#[test]
+fn unsupported_record_types_are_skipped() {
+    let changes = Changes {
+        create: Some(vec![endpoint("a.local", RecordType::TXT, &["\"heritage=external-dns\""])]),
+        ..changes()
+    };
+    let (records, report) = apply_changes(&HashMap::new(), &changes, &ChangePolicy::default()).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(report.skipped.len(), 1);
+}
+
+#[test]
+fn invalid_targets_fail_validation() {
+    let changes = Changes {
+        create: Some(vec![
+            endpoint("a.local", RecordType::A, &["not-an-ip"]),
+            endpoint("b.local", RecordType::A, &["2001:db8::1"]),
+            endpoint("*.c.local", RecordType::A, &["10.0.0.1"]),
+        ]),
+        ..changes()
+    };
+    match apply_changes(&HashMap::new(), &changes, &ChangePolicy::default()) {
+        Err(Error::Validation { details, .. }) => assert_eq!(details.len(), 3),
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn delete_ignores_malformed_targets() {
+    // an address the hosts file kept although it isn't valid
+    let current = hosts(&[("a.local", &["10.0.0.999"])]);
+    let changes = Changes { delete: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.999"])]), ..changes() };
+    let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(report.deleted.len(), 1);
+}
+
+#[test]
+fn current_records_are_not_modified() {
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes { delete: Some(vec![endpoint("a.local", RecordType::A, &[])]), ..changes() };
+    let (records, _) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(current, hosts(&[("a.local", &["10.0.0.1"])]));
+}
+
+fn name_strategy() -> impl Strategy<Value = String> {
+    "[a-e]{1,3}\\.local"
+}
+
+fn ipv4_strategy() -> impl Strategy<Value = String> {
+    (0u8..4, 0u8..4).prop_map(|(a, b)| format!("10.0.{a}.{b}"))
+}
+
+fn ipv6_strategy() -> impl Strategy<Value = String> {
+    (0u16..8).prop_map(|a| Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, a).to_string())
+}
+
+fn endpoint_strategy() -> impl Strategy<Value = Endpoint> {
+    prop_oneof![
+        (name_strategy(), prop::collection::vec(ipv4_strategy(), 1..4))
+            .prop_map(|(name, targets)| Endpoint { targets, ..endpoint(&name, RecordType::A, &[]) }),
+        (name_strategy(), prop::collection::vec(ipv6_strategy(), 1..4))
+            .prop_map(|(name, targets)| Endpoint { targets, ..endpoint(&name, RecordType::AAAA, &[]) }),
+    ]
+}
+
+fn hosts_strategy() -> impl Strategy<Value = HostRecords> {
+    prop::collection::hash_map(
+        name_strategy(),
+        prop::collection::hash_set(prop_oneof![ipv4_strategy(), ipv6_strategy()], 1..4),
+        0..6,
+    )
+}
+
+fn changes_strategy() -> impl Strategy<Value = Changes> {
+    (
+        prop::collection::vec(endpoint_strategy(), 0..4),
+        prop::collection::vec(endpoint_strategy(), 0..4),
+        prop::collection::vec(endpoint_strategy(), 0..4),
+        prop::collection::vec(endpoint_strategy(), 0..4),
+    ).prop_map(|(create, update_old, update_new, delete)| Changes {
+        create: Some(create),
+        update_old: Some(update_old),
+        update_new: Some(update_new),
+        delete: Some(delete),
+    })
+}
+
+#[test]
+fn owned_names_are_removed_from_changes() {
+    let owned = ["pinned.local".to_string()].into();
+    let changes = Changes {
+        create: Some(vec![endpoint("Pinned.Local.", RecordType::A, &["10.0.0.1"]), endpoint("a.local", RecordType::A, &["10.0.0.2"])]),
+        update_old: Some(vec![endpoint("pinned.local", RecordType::A, &["10.0.0.1"]), endpoint("b.local", RecordType::A, &["10.0.0.3"])]),
+        update_new: Some(vec![endpoint("pinned.local", RecordType::A, &["10.0.0.9"]), endpoint("b.local", RecordType::A, &["10.0.0.4"])]),
+        delete: None,
+    };
+    let (kept, skipped) = without_owned(&changes, &owned);
+    assert_eq!(kept.create.unwrap().len(), 1);
+    assert_eq!(kept.update_old.unwrap()[0].dns_name, "b.local");
+    assert_eq!(kept.update_new.unwrap()[0].dns_name, "b.local");
+    assert!(kept.delete.is_none());
+    assert_eq!(skipped.len(), 2);
+    assert!(skipped.iter().all(|s| s.name == "pinned.local"));
+}
+
+proptest! {
+    #[test]
+    fn prop_no_empty_names(current in hosts_strategy(), changes in changes_strategy()) {
+        let (records, _) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+        prop_assert!(records.values().all(|ips| !ips.is_empty()));
+    }
+
+    #[test]
+    fn prop_deterministic(current in hosts_strategy(), changes in changes_strategy()) {
+        let first = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+        let second = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+        prop_assert_eq!(first, second);
+    }
+
+    #[test]
+    fn prop_creates_are_present(current in hosts_strategy(), create in prop::collection::vec(endpoint_strategy(), 0..6)) {
+        let changes = Changes { create: Some(create.clone()), ..changes() };
+        let policy = ChangePolicy { on_create_existing: CreateExisting::Merge, ..Default::default() };
+        let (records, _) = apply_changes(&current, &changes, &policy).unwrap();
+        for endpoint in &create {
+            let ips = records.get(&endpoint.dns_name).expect("created name");
+            prop_assert!(endpoint.targets.iter().all(|t| ips.contains(t)));
+        }
+    }
+
+    #[test]
+    fn prop_create_then_delete_restores(current in hosts_strategy(), create in prop::collection::vec(endpoint_strategy(), 0..6)) {
+        // only create names absent from the current records
+        let create: Vec<Endpoint> = create.into_iter().filter(|e| !current.contains_key(&e.dns_name)).collect();
+        let created = Changes { create: Some(create.clone()), ..changes() };
+        let (records, _) = apply_changes(&current, &created, &ChangePolicy::default()).unwrap();
+        let deleted = Changes { delete: Some(create), ..changes() };
+        let (records, _) = apply_changes(&records, &deleted, &ChangePolicy::default()).unwrap();
+        prop_assert_eq!(records, current);
+    }
+
+    #[test]
+    fn prop_deletes_are_absent(current in hosts_strategy(), delete in prop::collection::vec(endpoint_strategy(), 0..6)) {
+        let changes = Changes { delete: Some(delete.clone()), ..changes() };
+        let (records, report) = apply_changes(&current, &changes, &ChangePolicy::default()).unwrap();
+        let remaining: usize = records.values().map(HashSet::len).sum();
+        let before: usize = current.values().map(HashSet::len).sum();
+        prop_assert!(remaining <= before);
+        prop_assert!(report.created.is_empty() && report.updated.is_empty());
+        for endpoint in &delete {
+            let family_left = records.get(&endpoint.dns_name)
+                .is_some_and(|ips| ips.iter().any(|ip| ip.contains(':') == (endpoint.record_type == RecordType::AAAA)));
+            prop_assert!(!family_left);
+        }
+    }
+
+    #[test]
+    fn prop_update_roundtrip(current in hosts_strategy(), pair in (endpoint_strategy(), prop::collection::vec(ipv4_strategy(), 1..4))) {
+        let (old, new_targets) = pair;
+        prop_assume!(old.record_type == RecordType::A);
+        // the old record must exist exactly as described, and the new targets must be new
+        let mut current = current;
+        current.insert(old.dns_name.clone(), old.targets.iter().cloned().collect());
+        let new_targets: Vec<String> = new_targets.into_iter().filter(|t| !old.targets.contains(t)).collect();
+        prop_assume!(!new_targets.is_empty());
+        let new = Endpoint { targets: new_targets, ..old.clone() };
+
+        let forward = Changes { update_old: Some(vec![old.clone()]), update_new: Some(vec![new.clone()]), ..changes() };
+        let (records, _) = apply_changes(&current, &forward, &ChangePolicy::default()).unwrap();
+        let backward = Changes { update_old: Some(vec![new]), update_new: Some(vec![old]), ..changes() };
+        let (records, _) = apply_changes(&records, &backward, &ChangePolicy::default()).unwrap();
+        prop_assert_eq!(records, current);
+    }
+}
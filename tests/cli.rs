@@ -0,0 +1,49 @@
+use host_webhook_provider::cli::{describe_changes, parse, Format};
+use host_webhook_provider::hosts::validate_hosts;
+use host_webhook_provider::error::Error;
+
+#[test]
+fn json_import_is_normalized() {
+    let content = r#"[
+        {"dnsName": "A.Local.", "recordType": "A", "targets": ["10.0.0.1"]},
+        {"dnsName": "a.local", "recordType": "AAAA", "targets": ["2001:db8::1"]}
+    ]"#;
+    let records = parse(content, Format::Json).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records["a.local"].len(), 2);
+}
+
+#[test]
+fn json_import_rejects_unsupported_records() {
+    let content = r#"[{"dnsName": "a.local", "recordType": "CNAME", "targets": ["b.local"]}]"#;
+    assert!(matches!(parse(content, Format::Json), Err(Error::Validation { .. })));
+}
+
+#[test]
+fn hosts_import_skips_comments() {
+    let records = parse("# backup\n10.0.0.1 a.local\n10.0.0.2 b.local\n", Format::Hosts).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records["b.local"].contains("10.0.0.2"));
+}
+
+#[test]
+fn validation_reports_line_numbers() {
+    let errors = validate_hosts("# header\n\n10.0.0.1 a.local\nnot-a-line\n10.0.0.300 b.local\n");
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].starts_with("line 4:"));
+    assert!(errors[1].starts_with("line 5:"));
+}
+
+#[test]
+fn diff_ignores_order_and_duplicates() {
+    let a = parse("10.0.0.1 a.local\n10.0.0.2 a.local\n10.0.0.3 b.local\n", Format::Hosts).unwrap();
+    let b = parse("10.0.0.3 b.local\n10.0.0.2 a.local\n10.0.0.1 a.local\n10.0.0.1 a.local\n", Format::Hosts).unwrap();
+    assert_eq!(describe_changes(&a, &b), "");
+}
+
+#[test]
+fn diff_lists_changed_names() {
+    let a = parse("10.0.0.1 a.local\n10.0.0.2 b.local\n", Format::Hosts).unwrap();
+    let b = parse("10.0.0.9 a.local\n10.0.0.3 c.local\n", Format::Hosts).unwrap();
+    assert_eq!(describe_changes(&a, &b), "~ a.local 10.0.0.1 -> 10.0.0.9\n- b.local 10.0.0.2\n+ c.local 10.0.0.3\n");
+}
@@ -0,0 +1,27 @@
+// Fixtures shared by the integration tests, each test crate uses a part of them
+#![allow(dead_code)]
+
+use host_webhook_provider::hosts::{HostRecords, HostsSnapshot};
+use host_webhook_provider::records::{Endpoint, RecordType};
+
+pub fn endpoint(name: &str, record_type: RecordType, targets: &[&str]) -> Endpoint {
+    Endpoint {
+        dns_name: name.to_string(),
+        targets: targets.iter().map(|t| t.to_string()).collect(),
+        record_type,
+        set_identifier: None,
+        record_t_t_l: None,
+        labels: None,
+        provider_specific: None,
+    }
+}
+
+pub fn hosts(entries: &[(&str, &[&str])]) -> HostRecords {
+    entries.iter()
+        .map(|(name, ips)| (name.to_string(), ips.iter().map(|ip| ip.to_string()).collect()))
+        .collect()
+}
+
+pub fn snapshot(records: HostRecords) -> HostsSnapshot {
+    HostsSnapshot { records, ..Default::default() }
+}
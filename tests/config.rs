@@ -0,0 +1,32 @@
+use clap::Parser;
+use host_webhook_provider::config::DomainFilter;
+
+fn filter(args: &[&str]) -> Result<DomainFilter, clap::Error> {
+    DomainFilter::try_parse_from(std::iter::once("filter").chain(args.iter().copied()))
+}
+
+#[test]
+fn invalid_regex_is_rejected_when_parsed() {
+    assert!(filter(&["--regex", "(a.local"]).is_err());
+    assert!(filter(&["--regex-exclusion", "[z-a]"]).is_err());
+    assert!(filter(&["--regex", ""]).is_ok());
+}
+
+#[test]
+fn regex_exclusion_takes_precedence() {
+    let only_regex = filter(&["--regex", r"^a\..*\.local$"]).unwrap();
+    assert!(only_regex.matches("a.dev.local."));
+    assert!(!only_regex.matches("b.dev.local"));
+
+    let with_exclusion = filter(&["--regex", r"^a\.", "--regex-exclusion", r"\.dev\.local$"]).unwrap();
+    assert!(!with_exclusion.matches("a.dev.local"));
+    assert!(with_exclusion.matches("b.prod.local"));
+}
+
+#[test]
+fn regex_is_sent_as_its_pattern() {
+    let filter = filter(&["--regex", r"^a\.local$"]).unwrap();
+    let json = serde_json::to_value(&filter).unwrap();
+    assert_eq!(json["regex"], r"^a\.local$");
+    assert_eq!(json["regexExclusion"], "");
+}
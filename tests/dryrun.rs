@@ -0,0 +1,99 @@
+use host_webhook_provider::changes::{ChangePolicy, CreateExisting};
+use host_webhook_provider::dryrun::{hosts_diff, plan_changes, PlannedName, PLAN_LABELS};
+use host_webhook_provider::hosts::HostsSnapshot;
+use host_webhook_provider::records::{Changes, RecordType};
+
+mod common;
+use common::{endpoint, hosts, snapshot};
+
+fn planned(name: &str, before: &[&str], after: &[&str]) -> PlannedName {
+    PlannedName {
+        name: name.to_string(),
+        before: before.iter().map(|t| t.to_string()).collect(),
+        after: after.iter().map(|t| t.to_string()).collect(),
+    }
+}
+
+#[test]
+fn identical_records_have_empty_diff() {
+    let records = hosts(&[("a.local", &["10.0.0.1", "10.0.0.2"]), ("b.local", &["2001:db8::1"])]);
+    assert_eq!(hosts_diff(&records, &records.clone(), PLAN_LABELS), "");
+}
+
+#[test]
+fn diff_shows_added_and_removed_lines() {
+    let before = hosts(&[("a.local", &["10.0.0.1"]), ("b.local", &["10.0.0.2"])]);
+    let after = hosts(&[("a.local", &["10.0.0.1"]), ("c.local", &["10.0.0.3"])]);
+    let diff = hosts_diff(&before, &after, PLAN_LABELS);
+    assert!(diff.starts_with("--- current\n+++ planned\n"));
+    assert!(diff.contains("\n-10.0.0.2 b.local\n"));
+    assert!(diff.contains("\n+10.0.0.3 c.local\n"));
+    assert!(diff.contains("\n 10.0.0.1 a.local\n"));
+}
+
+#[test]
+fn plan_lists_added_removed_and_modified_names() {
+    let current = hosts(&[("a.local", &["10.0.0.1"]), ("b.local", &["10.0.0.2"])]);
+    let changes = Changes {
+        create: Some(vec![endpoint("c.local", RecordType::A, &["10.0.0.3"]), endpoint("a.local", RecordType::AAAA, &["2001:db8::1"])]),
+        update_old: None,
+        update_new: None,
+        delete: Some(vec![endpoint("b.local", RecordType::A, &[])]),
+    };
+    let plan = plan_changes(&snapshot(current.clone()), &changes, &ChangePolicy::default());
+    assert!(plan.valid);
+    assert!(plan.errors.is_empty());
+    assert_eq!(plan.added, vec![planned("c.local", &[], &["10.0.0.3"])]);
+    assert_eq!(plan.removed, vec![planned("b.local", &["10.0.0.2"], &[])]);
+    assert_eq!(plan.modified, vec![planned("a.local", &["10.0.0.1"], &["10.0.0.1", "2001:db8::1"])]);
+    assert!(plan.diff.contains("+10.0.0.3 c.local"));
+}
+
+#[test]
+fn plan_reports_rejections_without_changes() {
+    let policy = ChangePolicy { on_create_existing: CreateExisting::Reject, ..Default::default() };
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let changes = Changes {
+        create: Some(vec![endpoint("a.local", RecordType::A, &["10.0.0.9"])]),
+        update_old: None,
+        update_new: None,
+        delete: None,
+    };
+    let plan = plan_changes(&snapshot(current), &changes, &policy);
+    assert!(!plan.valid);
+    assert_eq!(plan.errors.len(), 1);
+    assert!(plan.added.is_empty() && plan.removed.is_empty() && plan.modified.is_empty());
+    assert!(plan.diff.is_empty());
+}
+
+#[test]
+fn plan_reports_every_invalid_target() {
+    let changes = Changes {
+        create: Some(vec![endpoint("a.local", RecordType::A, &["2001:db8::1", "not-an-ip"])]),
+        update_old: None,
+        update_new: None,
+        delete: None,
+    };
+    let plan = plan_changes(&snapshot(hosts(&[])), &changes, &ChangePolicy::default());
+    assert!(!plan.valid);
+    assert_eq!(plan.errors.len(), 2);
+}
+
+#[test]
+fn plan_skips_operator_owned_names() {
+    let current = HostsSnapshot {
+        records: hosts(&[("pinned.local", &["10.0.0.1"])]),
+        owned: ["pinned.local".to_string()].into(),
+        ..Default::default()
+    };
+    let changes = Changes {
+        create: None,
+        update_old: None,
+        update_new: None,
+        delete: Some(vec![endpoint("pinned.local", RecordType::A, &[])]),
+    };
+    let plan = plan_changes(&current, &changes, &ChangePolicy::default());
+    assert!(plan.valid);
+    assert!(plan.removed.is_empty());
+    assert_eq!(plan.skipped[0].reason, "name is operator-owned");
+}
@@ -0,0 +1,52 @@
+use host_webhook_provider::error::Error;
+use salvo::http::{ParseError, StatusCode};
+use serde_json::json;
+use std::sync::Arc;
+
+fn kube_error(code: u16) -> Error {
+    Error::Kube(kube::Error::Api(kube::core::ErrorResponse {
+        status: "Failure".into(),
+        message: "configmaps \"hosts\" failed".into(),
+        reason: "Reason".into(),
+        code,
+    }))
+}
+
+// Code, status and details are a contract with the callers, the messages aren't
+#[test]
+fn errors_map_to_stable_codes_and_statuses() {
+    let cases = [
+        (kube_error(409), "conflict", StatusCode::CONFLICT, vec!["reason=Reason", "status=409"]),
+        (kube_error(404), "kube_error", StatusCode::NOT_FOUND, vec!["reason=Reason", "status=404"]),
+        (kube_error(500), "kube_error", StatusCode::BAD_GATEWAY, vec!["reason=Reason", "status=500"]),
+        (Error::KubeConfig("no context".into()), "kube_config_error", StatusCode::INTERNAL_SERVER_ERROR, vec![]),
+        (Error::Parse(ParseError::EmptyBody), "parse_error", StatusCode::BAD_REQUEST, vec![]),
+        (Error::validation("invalid", vec!["a.local: bad target".into()]), "validation_failed", StatusCode::UNPROCESSABLE_ENTITY, vec!["a.local: bad target"]),
+        (Error::Conflict("modified".into()), "conflict", StatusCode::CONFLICT, vec![]),
+        (Error::Filter { names: vec!["a.example".into()] }, "filter_violation", StatusCode::FORBIDDEN, vec!["a.example"]),
+        (Error::NotAcceptable("application/x"), "not_acceptable", StatusCode::NOT_ACCEPTABLE, vec![]),
+        (Error::UnsupportedMediaType("application/x"), "unsupported_media_type", StatusCode::UNSUPPORTED_MEDIA_TYPE, vec![]),
+        (Error::Unauthorized("no token"), "unauthorized", StatusCode::UNAUTHORIZED, vec![]),
+        (Error::NotFound("revision 3".into()), "not_found", StatusCode::NOT_FOUND, vec![]),
+        (Error::Shared(Arc::new(Error::Conflict("modified".into()))), "conflict", StatusCode::CONFLICT, vec![]),
+        (Error::Unavailable("writer stopped".into()), "unavailable", StatusCode::SERVICE_UNAVAILABLE, vec![]),
+        (Error::Io(std::io::Error::other("disk")), "io_error", StatusCode::INTERNAL_SERVER_ERROR, vec![]),
+        (Error::Json(serde_json::from_str::<u8>("x").unwrap_err()), "internal_error", StatusCode::INTERNAL_SERVER_ERROR, vec![]),
+    ];
+    for (error, code, status, details) in cases {
+        assert_eq!(error.code(), code, "{error}");
+        assert_eq!(error.status_code(), status, "{error}");
+        assert_eq!(serde_json::to_value(error.body()).unwrap(), json!({
+            "code": code,
+            "message": error.to_string(),
+            "details": details,
+        }));
+    }
+}
+
+#[test]
+fn shared_errors_keep_the_message_of_the_cause() {
+    let error = Error::Shared(Arc::new(Error::Filter { names: vec!["a.example".into()] }));
+    assert_eq!(error.to_string(), "domain filter violation: a.example");
+    assert_eq!(error.details(), vec!["a.example"]);
+}
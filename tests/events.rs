@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+use host_webhook_provider::events::{EventAction, EventAggregator, NORMAL, WARNING};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+const HOSTS: &str = "dns/hosts";
+
+#[test]
+fn first_event_is_created() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let action = aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "1 created", Instant::now());
+    assert_eq!(action, EventAction::Create { count: 1 });
+}
+
+#[test]
+fn writes_with_different_counts_make_one_event() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let start = Instant::now();
+    let mut actions = Vec::new();
+    for i in 1..=10 {
+        let message = format!("hosts updated: {i} created, 0 updated, 0 deleted names");
+        actions.push(aggregator.observe(NORMAL, "HostsUpdated", HOSTS, &message, start + Duration::from_secs(i)));
+    }
+    assert_eq!(actions[0], EventAction::Create { count: 1 });
+    assert!(actions[1..].iter().all(|action| *action == EventAction::Suppress));
+    aggregator.created(NORMAL, "HostsUpdated", HOSTS, "hosts.1".into());
+
+    // the suppressed occurrences are published as the count of the same Event
+    let pending = aggregator.flush(start + INTERVAL);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].action, EventAction::Update { name: "hosts.1".into(), count: 10 });
+    assert_eq!(pending[0].message, "hosts updated: 10 created, 0 updated, 0 deleted names");
+    // nothing left until new occurrences
+    assert!(aggregator.flush(start + INTERVAL * 2).is_empty());
+}
+
+#[test]
+fn repeated_event_is_updated_after_the_interval() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let start = Instant::now();
+    aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "1 created", start);
+    aggregator.created(NORMAL, "HostsUpdated", HOSTS, "hosts.1".into());
+    for i in 1..=3 {
+        let action = aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "2 created", start + Duration::from_secs(i));
+        assert_eq!(action, EventAction::Suppress);
+    }
+    let action = aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "3 created", start + INTERVAL);
+    assert_eq!(action, EventAction::Update { name: "hosts.1".into(), count: 5 });
+}
+
+#[test]
+fn events_of_different_reasons_or_objects_are_apart() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let now = Instant::now();
+    assert_eq!(aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "1 created", now), EventAction::Create { count: 1 });
+    assert_eq!(aggregator.observe(WARNING, "HostsUpdateFailed", HOSTS, "failed", now), EventAction::Create { count: 1 });
+    assert_eq!(aggregator.observe(NORMAL, "HostsUpdated", "dns/other", "1 created", now), EventAction::Create { count: 1 });
+}
+
+#[test]
+fn events_of_one_object_are_rate_limited() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let now = Instant::now();
+    let created = (0..30)
+        .map(|i| aggregator.observe(WARNING, &format!("Reason{i}"), HOSTS, "failed", now))
+        .filter(|action| *action != EventAction::Suppress)
+        .count();
+    assert_eq!(created, 25);
+    // another object has its own limit
+    assert_eq!(aggregator.observe(WARNING, "Reason0", "dns/other", "failed", now), EventAction::Create { count: 1 });
+    // the events over the limit are created on the next flush
+    let pending = aggregator.flush(now + INTERVAL);
+    assert_eq!(pending.len(), 5);
+    assert!(pending.iter().all(|p| p.action == EventAction::Create { count: 1 }));
+}
+
+#[test]
+fn event_is_created_again_after_the_aggregation_window() {
+    let mut aggregator = EventAggregator::new(INTERVAL);
+    let start = Instant::now();
+    aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "1 created", start);
+    aggregator.created(NORMAL, "HostsUpdated", HOSTS, "hosts.1".into());
+    let action = aggregator.observe(NORMAL, "HostsUpdated", HOSTS, "1 created", start + Duration::from_secs(3600));
+    assert_eq!(action, EventAction::Create { count: 1 });
+}
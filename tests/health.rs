@@ -0,0 +1,52 @@
+use std::time::Duration;
+use host_webhook_provider::health::{heartbeat_check, required_access, Check, ProbeReport, MAX_HEARTBEAT_LAG};
+use host_webhook_provider::reload::Workload;
+
+#[test]
+fn late_heartbeat_fails_the_runtime_check() {
+    assert!(heartbeat_check(Duration::from_millis(800)).ok);
+    let check = heartbeat_check(MAX_HEARTBEAT_LAG + Duration::from_secs(1));
+    assert!(!check.ok);
+    assert!(check.message.contains("wedged"));
+}
+
+#[test]
+fn one_failed_check_fails_the_probe() {
+    let report = ProbeReport::new(vec![
+        Check::new("kubernetes", Ok("API server v1.31.0".into())),
+        Check::new("rbac", Err("patch forbidden on ConfigMap dns/hosts".into())),
+    ]);
+    assert!(!report.is_ok());
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["status"], "failed");
+    assert_eq!(json["checks"][1]["ok"], false);
+
+    let report = ProbeReport::new(vec![Check::new("cache", Ok("synced".into()))]);
+    assert_eq!(report.status, "ok");
+}
+
+#[test]
+fn required_access_follows_the_configuration() {
+    let access = required_access("dns", "hosts", false, false, false, false, &[]);
+    assert_eq!(access.len(), 1);
+    assert_eq!(access[0].to_string(), "get, patch, create on configmaps dns/hosts");
+
+    let workloads: Vec<Workload> = vec!["deployment/coredns".parse().unwrap(), "kube-system/ds/node-dns".parse().unwrap()];
+    let access: Vec<String> = required_access("dns", "hosts", true, true, false, false, &workloads).iter().map(|a| a.to_string()).collect();
+    assert_eq!(access, [
+        "get, patch, create, list, watch on configmaps dns/hosts",
+        "get, patch, create, delete on configmaps dns/*",
+        "patch on deployments dns/coredns",
+        "patch on daemonsets kube-system/node-dns",
+    ]);
+    let access = required_access("dns", "hosts", false, false, false, false, &workloads);
+    assert_eq!(access[1].group, "apps");
+
+    // the revisions are listed
+    let access = required_access("dns", "hosts", false, false, true, false, &[]);
+    assert_eq!(access[1].to_string(), "get, patch, create, delete, list on configmaps dns/*");
+
+    // the events are created and patched
+    let access = required_access("dns", "hosts", false, false, false, true, &[]);
+    assert_eq!(access[1].to_string(), "create, patch on events dns/*");
+}
@@ -0,0 +1,65 @@
+use host_webhook_provider::history::{
+    diff_revisions, oldest_kept, revision_configmaps, revision_name, split_lines, Revision, RevisionInfo,
+    HISTORY_OF_LABEL, REVISION_LABEL,
+};
+
+fn revision(revision: u64, hosts: &str) -> Revision {
+    Revision { revision, at: "2024-01-01T00:00:00Z".into(), summary: String::new(), hosts: hosts.into() }
+}
+
+#[test]
+fn content_is_split_at_line_boundaries() {
+    let hosts = "10.0.0.1 a.local\n10.0.0.2 b.local\n10.0.0.3 c.local\n";
+    assert_eq!(split_lines(hosts, 0), vec![hosts]);
+    assert_eq!(split_lines(hosts, 1024), vec![hosts]);
+    assert_eq!(split_lines(hosts, 40), vec!["10.0.0.1 a.local\n10.0.0.2 b.local\n", "10.0.0.3 c.local\n"]);
+    // a line longer than the limit is a part alone
+    assert_eq!(split_lines(hosts, 5).len(), 3);
+    assert_eq!(split_lines("", 5), vec![""]);
+    assert_eq!(split_lines(hosts, 5).concat(), hosts);
+}
+
+#[test]
+fn revisions_are_stored_in_their_own_configmaps() {
+    let mut written = revision(7, "10.0.0.1 a.local\n10.0.0.2 b.local\n10.0.0.3 c.local\n");
+    written.summary = "hosts updated: 3 created".into();
+    let configmaps = revision_configmaps("hosts-history", "hosts", &written, 40);
+    let names: Vec<_> = configmaps.iter().map(|cm| cm.metadata.name.clone().unwrap()).collect();
+    assert_eq!(names, ["hosts-history-7", "hosts-history-7-1"]);
+    assert_eq!(revision_name("hosts-history", 7, 1), "hosts-history-7-1");
+    for cm in &configmaps {
+        let labels = cm.metadata.labels.as_ref().unwrap();
+        assert_eq!(labels[HISTORY_OF_LABEL], "hosts");
+        assert_eq!(labels[REVISION_LABEL], "7");
+    }
+
+    // the first ConfigMap describes the revision, the others are parts of the content
+    let info = RevisionInfo::of(&configmaps[0].metadata).unwrap();
+    assert_eq!((info.revision, info.lines, info.summary.as_str()), (7, 3, "hosts updated: 3 created"));
+    assert_eq!(info.at, "2024-01-01T00:00:00Z");
+    assert!(RevisionInfo::of(&configmaps[1].metadata).is_none());
+    let hosts: String = configmaps.iter().map(|cm| cm.data.as_ref().unwrap()["hosts"].clone()).collect();
+    assert_eq!(hosts, written.hosts);
+}
+
+#[test]
+fn only_the_latest_revisions_are_kept() {
+    assert_eq!(oldest_kept(vec![3, 1, 2, 5, 4], 3), Some(3));
+    assert_eq!(oldest_kept(vec![1, 2], 3), Some(1));
+    assert_eq!(oldest_kept(vec![1, 2], 0), Some(2));
+    assert_eq!(oldest_kept(vec![], 3), None);
+}
+
+#[test]
+fn diff_between_revisions_lists_names() {
+    let from = revision(1, "10.0.0.1 a.local\n10.0.0.2 b.local\n");
+    let to = revision(2, "10.0.0.1 a.local\n10.0.0.3 a.local\n10.0.0.4 c.local\n");
+    let diff = diff_revisions(&from, &to);
+    assert_eq!((diff.from, diff.to), (1, 2));
+    assert_eq!(diff.added.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(), vec!["c.local"]);
+    assert_eq!(diff.removed.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(), vec!["b.local"]);
+    assert_eq!(diff.modified.len(), 1);
+    assert_eq!(diff.modified[0].after, vec!["10.0.0.1", "10.0.0.3"]);
+    assert!(diff.diff.starts_with("--- revision 1\n+++ revision 2\n"));
+    assert!(diff.diff.contains("-10.0.0.2 b.local"));
+}
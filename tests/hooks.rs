@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use host_webhook_provider::hooks::{exec, hook_env, post, with_retries, HookPayload, Hooks};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[test]
+fn changes_are_passed_in_the_environment() {
+    let payload = HookPayload {
+        configmap: "hosts".into(),
+        namespace: "dns".into(),
+        checksum: "0123456789abcdef".into(),
+        summary: "hosts updated: 2 created, 0 updated, 1 deleted names".into(),
+        created: vec!["a.local".into(), "b.local".into()],
+        updated: vec![],
+        deleted: vec!["c.local".into()],
+    };
+    let env = hook_env(&payload);
+    let get = |key: &str| env.iter().find(|(k, _)| *k == key).map(|(_, v)| v.as_str());
+    assert_eq!(get("HOSTS_CREATED"), Some("a.local,b.local"));
+    assert_eq!(get("HOSTS_UPDATED"), Some(""));
+    assert_eq!(get("HOSTS_DELETED"), Some("c.local"));
+    assert_eq!(get("HOSTS_CHECKSUM"), Some("0123456789abcdef"));
+
+    let json: serde_json::Value = serde_json::from_str(get("HOSTS_CHANGES_JSON").unwrap()).unwrap();
+    assert_eq!(json["configmap"], "hosts");
+    assert_eq!(json["created"][1], "b.local");
+}
+
+fn payload(checksum: &str) -> HookPayload {
+    HookPayload { checksum: checksum.into(), ..Default::default() }
+}
+
+#[tokio::test]
+async fn commands_fail_on_exit_status_and_timeout() {
+    let timeout = Duration::from_secs(5);
+    assert_eq!(exec("test \"$HOSTS_CHECKSUM\" = c1", &payload("c1"), timeout).await, Ok(()));
+
+    let e = exec("echo out; echo err >&2; exit 3", &payload("c1"), timeout).await.unwrap_err();
+    assert!(e.contains("exit status: 3"), "{e}");
+    assert!(e.contains("stdout: \"out\"") && e.contains("stderr: \"err\""), "{e}");
+
+    let started = Instant::now();
+    let e = exec("sleep 10", &payload("c1"), Duration::from_secs(1)).await.unwrap_err();
+    assert_eq!(e, "timed out after 1s");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn failed_hooks_are_retried() {
+    let attempts = AtomicU32::new(0);
+    let result = with_retries("hook", 2, Duration::from_millis(1), || async {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        Err("failed".to_string())
+    }).await;
+    assert_eq!(result, Err("failed".into()));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+    let attempts = AtomicU32::new(0);
+    let result = with_retries("hook", 2, Duration::from_millis(1), || async {
+        match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 => Err("failed".to_string()),
+            _ => Ok(()),
+        }
+    }).await;
+    assert_eq!(result, Ok(()));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+}
+
+// Server answering each connection with the given status line, once per status
+async fn server(statuses: &'static [&'static str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        for status in statuses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            _ = stream.read(&mut request).await;
+            let response = format!("HTTP/1.1 {status}\r\ncontent-length: 4\r\nconnection: close\r\n\r\nbody");
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn callbacks_fail_on_error_status() {
+    let http = reqwest::Client::new();
+    let url = server(&["204 No Content", "503 Service Unavailable"]).await;
+    assert_eq!(post(&http, &url, &payload("c1")).await, Ok(()));
+    assert_eq!(post(&http, &url, &payload("c1")).await, Err("503 Service Unavailable: body".into()));
+
+    let http = reqwest::Client::builder().timeout(Duration::from_millis(200)).build().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    // accepted but never answered
+    assert!(post(&http, &url, &payload("c1")).await.is_err());
+}
+
+#[tokio::test]
+async fn pending_hooks_are_coalesced_to_the_latest_write() {
+    let hooks = Hooks::default();
+    hooks.notify(payload("c1"));
+    hooks.notify(payload("c2"));
+    assert_eq!(hooks.next().await.checksum, "c2");
+
+    let waiting = tokio::spawn({
+        let hooks = hooks.clone();
+        async move { hooks.next().await }
+    });
+    hooks.notify(payload("c3"));
+    assert_eq!(waiting.await.unwrap().checksum, "c3");
+}
+
+#[tokio::test]
+async fn close_waits_for_the_hooks_of_the_latest_write() {
+    let hooks = Hooks::default();
+    // nothing to wait for before a write
+    hooks.close().await;
+
+    hooks.notify(payload("c1"));
+    let closing = tokio::spawn({
+        let hooks = hooks.clone();
+        async move { hooks.close().await }
+    });
+    hooks.next().await;
+    // a newer write came while the hooks of the first one ran
+    hooks.notify(payload("c2"));
+    hooks.done();
+    tokio::task::yield_now().await;
+    assert!(!closing.is_finished());
+    hooks.next().await;
+    hooks.done();
+    closing.await.unwrap();
+}
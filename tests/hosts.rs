@@ -0,0 +1,44 @@
+use host_webhook_provider::hosts::{format_records, format_records_by, hosts_checksum, is_field_conflict, parse_hosts, HostsOrder};
+
+mod common;
+use common::hosts;
+
+#[test]
+fn rendering_is_sorted() {
+    let records = hosts(&[
+        ("b.local", &["2001:db8::1", "10.0.0.10", "10.0.0.9"]),
+        ("a.local", &["10.0.0.1"]),
+    ]);
+    assert_eq!(format_records(&records), "10.0.0.1 a.local\n10.0.0.9 b.local\n10.0.0.10 b.local\n2001:db8::1 b.local\n");
+}
+
+#[test]
+fn domain_order_groups_names() {
+    let records = hosts(&[
+        ("a.prod.example", &["10.0.0.1"]),
+        ("b.dev.example", &["10.0.0.2"]),
+        ("c.prod.example", &["10.0.0.3"]),
+    ]);
+    assert_eq!(format_records_by(&records, HostsOrder::Domain),
+        "10.0.0.2 b.dev.example\n10.0.0.1 a.prod.example\n10.0.0.3 c.prod.example\n");
+}
+
+#[test]
+fn same_records_render_identically() {
+    let first = parse_hosts("10.0.0.2 b.local\n10.0.0.1 a.local\n::1 a.local\n");
+    let second = parse_hosts("::1 a.local\n10.0.0.1 a.local\n10.0.0.2 b.local\n");
+    let (first, second) = (format_records(&first), format_records(&second));
+    assert_eq!(first, second);
+    assert_eq!(hosts_checksum(&first), hosts_checksum(&second));
+}
+
+#[test]
+fn conflicts_are_told_apart_by_the_version() {
+    // no precondition sent, only another field manager can conflict
+    assert!(is_field_conflict(None, Some("42")));
+    // the ConfigMap didn't change since the write
+    assert!(is_field_conflict(Some("42"), Some("42")));
+    // modified or deleted meanwhile, worth a retry
+    assert!(!is_field_conflict(Some("42"), Some("43")));
+    assert!(!is_field_conflict(Some("42"), None));
+}
@@ -0,0 +1,29 @@
+use host_webhook_provider::logging::{init_logging, log_filter, LogFormat};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+#[test]
+fn filter_defaults_to_the_debug_flag() {
+    assert_eq!(log_filter(None, false).unwrap().to_string(), "info");
+    assert_eq!(log_filter(Some(" "), true).unwrap().to_string(), "debug");
+    assert_eq!(log_filter(Some("warn,kube=debug"), true).unwrap().to_string(), "kube=debug,warn");
+}
+
+#[test]
+fn invalid_filter_is_rejected() {
+    let e = log_filter(Some("kube=loud"), false).unwrap_err();
+    assert_eq!(e.code(), "validation_failed");
+    assert!(!e.details().is_empty());
+}
+
+#[test]
+fn filter_reloads_at_runtime() {
+    let log_level = init_logging(LogFormat::Json, log_filter(None, false).unwrap(), BoxMakeWriter::new(std::io::sink), None);
+    assert!(!tracing::enabled!(tracing::Level::DEBUG));
+
+    assert_eq!(log_level.set_filter("debug").unwrap(), "debug");
+    assert!(tracing::enabled!(tracing::Level::DEBUG));
+
+    // the previous filter stays on error
+    assert!(log_level.set_filter("kube=loud").is_err());
+    assert_eq!(log_level.filter(), "debug");
+}
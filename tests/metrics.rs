@@ -0,0 +1,30 @@
+use host_webhook_provider::hosts::{parse_hosts, skipped_lines};
+use host_webhook_provider::metrics::{observe_records, observe_skipped_lines};
+use prometheus::Encoder;
+
+fn scrape() -> String {
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[test]
+fn records_and_skipped_lines_are_exported() {
+    let hosts = "10.0.0.1 a.prod.local\n2001:db8::1 a.prod.local\n10.0.0.2 b.prod.local\n10.0.0.3 c.dev.local\nnot a host line\n";
+    observe_records(&parse_hosts(hosts));
+    observe_skipped_lines(skipped_lines(hosts));
+    let metrics = scrape();
+    assert!(metrics.contains("host_webhook_records{domain=\"prod.local\",type=\"A\"} 2"));
+    assert!(metrics.contains("host_webhook_records{domain=\"prod.local\",type=\"AAAA\"} 1"));
+    assert!(metrics.contains("host_webhook_records{domain=\"dev.local\",type=\"A\"} 1"));
+    assert!(metrics.contains("host_webhook_hosts_lines_skipped 1"));
+
+    // the gauges follow the stored records, parsing again doesn't count
+    parse_hosts(hosts);
+    assert!(scrape().contains("host_webhook_hosts_lines_skipped 1"));
+    observe_records(&parse_hosts("10.0.0.3 c.dev.local\n"));
+    observe_skipped_lines(skipped_lines("10.0.0.3 c.dev.local\n"));
+    let metrics = scrape();
+    assert!(!metrics.contains("domain=\"prod.local\""));
+    assert!(metrics.contains("host_webhook_hosts_lines_skipped 0"));
+}
@@ -0,0 +1,78 @@
+use host_webhook_provider::negotiation::{is_acceptable, is_supported_content_type, negotiate, WEBHOOK_MEDIA_TYPE};
+use salvo::prelude::*;
+use salvo::test::TestClient;
+
+#[handler]
+async fn no_content(res: &mut Response) {
+    res.status_code(StatusCode::NO_CONTENT);
+}
+
+fn service() -> Service {
+    Service::new(Router::new().hoop(negotiate).push(Router::with_path("records").get(no_content).post(no_content)))
+}
+
+#[test]
+fn accept_allows_the_webhook_media_type() {
+    assert!(is_acceptable(None));
+    assert!(is_acceptable(Some("")));
+    assert!(is_acceptable(Some(WEBHOOK_MEDIA_TYPE)));
+    // the version is optional in a media range
+    assert!(is_acceptable(Some("application/external.dns.webhook+json")));
+    assert!(!is_acceptable(Some("application/external.dns.webhook+json;version=2")));
+    assert!(!is_acceptable(Some("application/json")));
+}
+
+#[test]
+fn accept_wildcards_match() {
+    assert!(is_acceptable(Some("*/*")));
+    assert!(is_acceptable(Some("application/*")));
+    assert!(!is_acceptable(Some("text/*")));
+    assert!(is_acceptable(Some("text/html, application/*;q=0.5")));
+}
+
+#[test]
+fn accept_with_zero_quality_excludes() {
+    assert!(!is_acceptable(Some("application/external.dns.webhook+json;version=1;q=0")));
+    assert!(!is_acceptable(Some("*/*;q=0")));
+    assert!(!is_acceptable(Some("application/*;q=0.0, text/plain")));
+    assert!(is_acceptable(Some("*/*;q=0, application/external.dns.webhook+json;q=0.1")));
+}
+
+#[test]
+fn malformed_accept_is_not_acceptable() {
+    assert!(!is_acceptable(Some("not a media type")));
+    assert!(!is_acceptable(Some(";;,")));
+    // malformed ranges are ignored, the valid ones still count
+    assert!(is_acceptable(Some("garbage, */*")));
+}
+
+#[test]
+fn content_type_requires_the_supported_version() {
+    assert!(is_supported_content_type(Some(WEBHOOK_MEDIA_TYPE)));
+    assert!(is_supported_content_type(Some("application/external.dns.webhook+json; version=1")));
+    assert!(!is_supported_content_type(Some("application/external.dns.webhook+json;version=2")));
+    assert!(!is_supported_content_type(Some("application/external.dns.webhook+json")));
+    assert!(!is_supported_content_type(Some("application/json")));
+    assert!(!is_supported_content_type(Some("not a media type")));
+    assert!(!is_supported_content_type(None));
+}
+
+#[tokio::test]
+async fn negotiation_statuses() {
+    let url = "http://127.0.0.1:8888/records";
+    let status = |res: salvo::Response| res.status_code.unwrap_or(StatusCode::OK);
+
+    let res = TestClient::post(url).add_header("content-type", WEBHOOK_MEDIA_TYPE, true).send(&service()).await;
+    assert_eq!(status(res), StatusCode::NO_CONTENT);
+
+    let res = TestClient::post(url)
+        .add_header("content-type", "application/external.dns.webhook+json;version=2", true)
+        .send(&service()).await;
+    assert_eq!(status(res), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let res = TestClient::get(url).add_header("accept", "application/json", true).send(&service()).await;
+    assert_eq!(status(res), StatusCode::NOT_ACCEPTABLE);
+
+    let res = TestClient::get(url).add_header("accept", "*/*", true).send(&service()).await;
+    assert_eq!(res.headers().get("content-type").unwrap(), WEBHOOK_MEDIA_TYPE);
+}
@@ -0,0 +1,23 @@
+use host_webhook_provider::reload::{restart_patch, Workload, WorkloadKind, POD_CHECKSUM_ANNOTATION};
+
+#[test]
+fn workloads_are_parsed() {
+    let workload: Workload = "deployment/coredns".parse().unwrap();
+    assert_eq!(workload, Workload { namespace: None, kind: WorkloadKind::Deployment, name: "coredns".into() });
+    let workload: Workload = "dns/ds/dnsmasq".parse().unwrap();
+    assert_eq!(workload, Workload { namespace: Some("dns".into()), kind: WorkloadKind::DaemonSet, name: "dnsmasq".into() });
+    assert_eq!(workload.to_string(), "dns/daemonset/dnsmasq");
+}
+
+#[test]
+fn invalid_workloads_are_rejected() {
+    assert!("statefulset/coredns".parse::<Workload>().is_err());
+    assert!("coredns".parse::<Workload>().is_err());
+    assert!("deployment/".parse::<Workload>().is_err());
+}
+
+#[test]
+fn restart_patch_sets_the_checksum() {
+    let patch = restart_patch("0123456789abcdef");
+    assert_eq!(patch["spec"]["template"]["metadata"]["annotations"][POD_CHECKSUM_ANNOTATION], "0123456789abcdef");
+}
@@ -0,0 +1,39 @@
+use host_webhook_provider::hosts::{format_records, HostRecords};
+use host_webhook_provider::shards::{fnv1a, plan_shards, shard_names};
+
+fn many_hosts(count: usize) -> HostRecords {
+    (0..count)
+        .map(|i| (format!("host-{i}.example.local"), [format!("10.0.{}.{}", i / 256, i % 256)].into()))
+        .collect()
+}
+
+#[test]
+fn small_records_are_not_sharded() {
+    let records = many_hosts(10);
+    assert_eq!(plan_shards(&records, 1024 * 1024), None);
+    assert_eq!(plan_shards(&many_hosts(10_000), 0), None);
+}
+
+#[test]
+fn large_records_are_split_under_the_threshold() {
+    let records = many_hosts(10_000);
+    let threshold = format_records(&records).len() / 3;
+    let shards = plan_shards(&records, threshold).unwrap();
+    assert!(shards.len() >= 3);
+    assert!(shards.iter().all(|shard| format_records(shard).len() <= threshold));
+
+    // reassembled shards give the records back
+    let mut reassembled = HostRecords::new();
+    for shard in &shards {
+        reassembled.extend(shard.clone());
+    }
+    assert_eq!(reassembled, records);
+}
+
+#[test]
+fn shard_assignment_is_stable() {
+    // FNV-1a reference values, shard names must not depend on the std hasher
+    assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(shard_names("hosts", "0123", 2), vec!["hosts-0123-0", "hosts-0123-1"]);
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::time::Duration;
+use host_webhook_provider::telemetry::{extract_context, otlp_enabled, tracer_provider};
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use salvo::http::HeaderMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::prelude::*;
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+const TRACE_ID: [u8; 16] = [0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e, 0x47, 0x36];
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn traceparent() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", TRACEPARENT.parse().unwrap());
+    headers
+}
+
+// Collector answering every request with 200, sends the path and body of each
+fn collector() -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            loop {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").unwrap();
+                _ = tx.send((path, body));
+            }
+        }
+    });
+    (format!("http://{addr}/v1/traces"), rx)
+}
+
+#[test]
+fn exporter_follows_the_otel_variables() {
+    assert!(!otlp_enabled(env(&[])));
+    assert!(otlp_enabled(env(&[("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318")])));
+    assert!(otlp_enabled(env(&[("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", "http://collector:4318/v1/traces")])));
+    assert!(otlp_enabled(env(&[("OTEL_TRACES_EXPORTER", "otlp")])));
+    assert!(!otlp_enabled(env(&[("OTEL_TRACES_EXPORTER", "none"), ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318")])));
+    assert!(!otlp_enabled(env(&[("OTEL_SDK_DISABLED", "true"), ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318")])));
+}
+
+#[test]
+fn spans_are_exported_in_the_caller_trace() {
+    let (endpoint, exported) = collector();
+    std::env::set_var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", &endpoint);
+    let provider = tracer_provider().unwrap().expect("OTLP exporter enabled by the endpoint");
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let parent = extract_context(&traceparent());
+        assert_eq!(parent.span().span_context().trace_id().to_bytes(), TRACE_ID);
+        let span = tracing::info_span!("webhook request");
+        span.set_parent(parent);
+        span.in_scope(|| tracing::info_span!("update_host").in_scope(|| {}));
+    });
+    provider.force_flush().unwrap();
+
+    let (path, body) = exported.recv_timeout(Duration::from_secs(10)).expect("no spans exported");
+    assert_eq!(path, "/v1/traces");
+    assert!(body.windows(TRACE_ID.len()).any(|w| w == TRACE_ID), "exported spans aren't in the caller trace");
+    assert!(body.windows(b"update_host".len()).any(|w| w == b"update_host"));
+    provider.shutdown().unwrap();
+}
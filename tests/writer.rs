@@ -0,0 +1,101 @@
+use host_webhook_provider::changes::{ChangePolicy, CreateExisting};
+use host_webhook_provider::error::Error;
+use host_webhook_provider::hosts::HostsSnapshot;
+use host_webhook_provider::records::{Changes, RecordType};
+use host_webhook_provider::writer::{apply_batch, Mutation};
+
+mod common;
+use common::{endpoint, hosts, snapshot};
+
+fn create(name: &str, targets: &[&str]) -> Mutation {
+    Mutation::Apply(Changes {
+        create: Some(vec![endpoint(name, RecordType::A, targets)]),
+        update_old: None,
+        update_new: None,
+        delete: None,
+    })
+}
+
+fn delete(name: &str) -> Mutation {
+    Mutation::Apply(Changes {
+        create: None,
+        update_old: None,
+        update_new: None,
+        delete: Some(vec![endpoint(name, RecordType::A, &[])]),
+    })
+}
+
+#[test]
+fn batch_applies_mutations_in_order() {
+    let mutations = [create("a.local", &["10.0.0.1"]), delete("a.local"), create("b.local", &["10.0.0.2"])];
+    let (after, results) = apply_batch(&HostsSnapshot::default(), &mutations, &ChangePolicy::default());
+    assert_eq!(after.records, hosts(&[("b.local", &["10.0.0.2"])]));
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().created.len(), 1);
+    assert_eq!(results[1].as_ref().unwrap().deleted.len(), 1);
+    assert_eq!(results[2].as_ref().unwrap().created.len(), 1);
+}
+
+#[test]
+fn failing_mutation_only_fails_its_caller() {
+    let policy = ChangePolicy { on_create_existing: CreateExisting::Reject, ..Default::default() };
+    let current = hosts(&[("a.local", &["10.0.0.1"])]);
+    let mutations = [create("a.local", &["10.0.0.9"]), create("b.local", &["10.0.0.2"])];
+    let (after, results) = apply_batch(&snapshot(current), &mutations, &policy);
+    assert_eq!(after.records, hosts(&[("a.local", &["10.0.0.1"]), ("b.local", &["10.0.0.2"])]));
+    assert!(matches!(results[0], Err(Error::Conflict(_))));
+    assert_eq!(results[1].as_ref().unwrap().created.len(), 1);
+}
+
+#[test]
+fn later_mutation_sees_earlier_ones() {
+    let policy = ChangePolicy { on_create_existing: CreateExisting::Merge, ..Default::default() };
+    let mutations = [create("a.local", &["10.0.0.1"]), create("a.local", &["10.0.0.2"])];
+    let (after, results) = apply_batch(&HostsSnapshot::default(), &mutations, &policy);
+    assert_eq!(after.records, hosts(&[("a.local", &["10.0.0.1", "10.0.0.2"])]));
+    let report = results[1].as_ref().unwrap();
+    assert_eq!(report.updated[0].before, vec!["10.0.0.1"]);
+}
+
+#[test]
+fn replace_reports_every_difference() {
+    let current = hosts(&[("a.local", &["10.0.0.1"]), ("b.local", &["10.0.0.2"])]);
+    let target = hosts(&[("a.local", &["10.0.0.9"]), ("c.local", &["10.0.0.3"])]);
+    let mutations = [Mutation::Replace { records: target.clone(), reason: "rollback to revision 1".into() }];
+    let (after, results) = apply_batch(&snapshot(current), &mutations, &ChangePolicy::default());
+    assert_eq!(after.records, target);
+    let report = results[0].as_ref().unwrap();
+    assert_eq!(report.created[0].name, "c.local");
+    assert_eq!(report.updated[0].name, "a.local");
+    assert_eq!(report.deleted[0].name, "b.local");
+}
+
+#[test]
+fn set_host_pins_the_name_against_external_dns() {
+    let current = snapshot(hosts(&[("a.local", &["10.0.0.1"])]));
+    let mutations = [
+        Mutation::SetHost { name: "a.local".into(), addresses: vec!["10.0.0.5".into()], owned: true },
+        delete("a.local"),
+        create("a.local", &["10.0.0.9"]),
+    ];
+    let (after, results) = apply_batch(&current, &mutations, &ChangePolicy::default());
+    assert_eq!(after.records, hosts(&[("a.local", &["10.0.0.5"])]));
+    assert!(after.owned.contains("a.local"));
+    assert_eq!(results[0].as_ref().unwrap().updated.len(), 1);
+    assert_eq!(results[1].as_ref().unwrap().skipped[0].reason, "name is operator-owned");
+    assert_eq!(results[2].as_ref().unwrap().skipped[0].reason, "name is operator-owned");
+}
+
+#[test]
+fn delete_host_releases_the_name() {
+    let current = HostsSnapshot {
+        records: hosts(&[("a.local", &["10.0.0.1"])]),
+        owned: ["a.local".to_string()].into(),
+        ..Default::default()
+    };
+    let mutations = [Mutation::DeleteHost { name: "a.local".into() }, Mutation::DeleteHost { name: "a.local".into() }];
+    let (after, results) = apply_batch(&current, &mutations, &ChangePolicy::default());
+    assert_eq!(after, HostsSnapshot::default());
+    assert_eq!(results[0].as_ref().unwrap().deleted.len(), 1);
+    assert!(matches!(results[1], Err(Error::NotFound(_))));
+}